@@ -1,7 +1,8 @@
 #![deny(clippy::as_conversions)]
 
 use std::{
-    env, fs,
+    env,
+    fs::File,
     io::BufReader,
     path::{Path, PathBuf},
     process::{Command, ExitCode, Stdio},
@@ -10,6 +11,7 @@ use std::{
 use clap::Parser;
 
 const GAME_DIR: &str = "dbgame-test";
+const ASSETS_DIR: &str = "assets";
 const ISO_LABEL: &str = "DBGAME-TEST";
 
 #[cfg(unix)]
@@ -21,6 +23,8 @@ const DREAMBOX_NAME: &str = "DreamboxVM.exe";
 enum Opt {
     Build(BuildOpt),
     Run(RunOpt),
+    Extract(ExtractOpt),
+    Append(AppendOpt),
 }
 
 #[derive(Parser)]
@@ -35,6 +39,108 @@ struct RunOpt {
     release: bool,
 }
 
+/// Copy the content of an image to a host directory
+#[derive(Parser)]
+struct ExtractOpt {
+    iso: PathBuf,
+    dir: PathBuf,
+}
+
+/// Append host files to an image as a new session, replacing the files at
+/// the same paths, instead of rebuilding the whole image
+#[derive(Parser)]
+struct AppendOpt {
+    iso: PathBuf,
+    /// Files to add, as `path=host_path` with `path` the one in the image
+    #[clap(required = true)]
+    files: Vec<String>,
+}
+
+/// Volume descriptor identifiers describing the game crate
+fn volume_opt() -> Result<iso::option::VolumeOpt, ()> {
+    let metadata = match cargo_metadata::MetadataCommand::new()
+        .current_dir(GAME_DIR)
+        .no_deps()
+        .exec()
+    {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            eprintln!("failed to read {GAME_DIR} metadata: {err}");
+            return Err(());
+        }
+    };
+    let Some(package) = metadata.packages.iter().find(|x| x.name == GAME_DIR)
+    else {
+        eprintln!("missing {GAME_DIR} package");
+        return Err(());
+    };
+
+    // a-characters identifiers are limited to 128 characters
+    let identifier = |value: String| {
+        Some(
+            iso::option::to_a_characters(&value)
+                .chars()
+                .take(128)
+                .collect(),
+        )
+    };
+
+    Ok(iso::option::VolumeOpt {
+        publisher_identifier: (!package.authors.is_empty())
+            .then(|| package.authors.join(", "))
+            .and_then(identifier),
+        data_preparer_identifier: identifier(format!(
+            "{} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )),
+        application_identifier: identifier(format!(
+            "{} {}",
+            package.name, package.version
+        )),
+        ..Default::default()
+    })
+}
+
+/// Options of the game images
+fn iso_opt() -> Result<iso::option::Opt, ()> {
+    Ok(iso::option::Opt {
+        eltorito_opt: iso::option::ElToritoOpt {
+            eltorito_boot: None,
+            no_emu_boot: true,
+            no_boot: true,
+            boot_info_table: false,
+            grub2_boot_info: false,
+            entries: Vec::new(),
+        },
+        embedded_boot: None,
+        grub2_mbr: None,
+        boot_load_size: 0,
+        protective_msdos_label: false,
+        gpt: false,
+        primary_volume_name: Some(ISO_LABEL.to_string()),
+        volume_opt: volume_opt()?,
+        interchange_level: iso::option::InterchangeLevel::Level3,
+        joliet: true,
+        normalize_attributes: true,
+        // The game is loaded first, keep it at the start of the disc
+        sort_rules: vec![iso::option::SortRule {
+            pattern: "main.wasm".to_string(),
+            weight: 1,
+            alignment_in_lb: 0,
+        }],
+        deduplicate: true,
+        compressed_files: Vec::new(),
+        // Lets QA check burned or downloaded images
+        checksums: true,
+        hidden_files: Vec::new(),
+        associated_files: Vec::new(),
+        host_filter: iso::option::HostFilter::default(),
+        timestamp: iso::option::Timestamp::SourceDateEpoch,
+        input_files: Vec::new(),
+    })
+}
+
 fn build(opt: BuildOpt) -> Result<PathBuf, ()> {
     let profile = if opt.release { "release" } else { "dev" };
     let mut command = Command::new("cargo");
@@ -66,30 +172,96 @@ fn build(opt: BuildOpt) -> Result<PathBuf, ()> {
     }
     child.wait().unwrap();
     let wasm = wasm.unwrap();
-    let main_wasm = wasm.with_file_name("main.wasm");
     let iso = wasm.with_extension("iso");
 
-    fs::copy(wasm, &main_wasm).unwrap();
-    let iso_content = iso::create_iso(&iso::option::Opt {
-        eltorito_opt: iso::option::ElToritoOpt {
-            eltorito_boot: None,
-            no_emu_boot: true,
-            no_boot: true,
-            boot_info_table: false,
-            grub2_boot_info: false,
-        },
-        embedded_boot: None,
-        grub2_mbr: None,
-        boot_load_size: 0,
-        protective_msdos_label: false,
-        primary_volume_name: Some(ISO_LABEL.to_string()),
-        input_files: vec![main_wasm.into_std_path_buf()],
-    })
-    .unwrap();
-    fs::write(&iso, iso_content).unwrap();
+    let mut iso_builder = iso::IsoBuilder::new();
+    if let Err(err) = iso_builder.add_host_file("main.wasm", wasm) {
+        eprintln!("failed to add main.wasm: {err}");
+        return Err(());
+    }
+
+    // Only the exported assets, not the Blender sources and their backups
+    let assets_filter = iso::option::HostFilter {
+        include: Vec::new(),
+        exclude: vec![
+            "**/*.blend".to_string(),
+            "**/*.blend?".to_string(),
+            "**/*~".to_string(),
+        ],
+        ignore_file: Some(".isoignore".to_string()),
+        dot_files: iso::option::DotFiles::Skip,
+    };
+    if let Err(err) = iso_builder.add_filtered_host_directory(
+        "assets",
+        ASSETS_DIR,
+        &assets_filter,
+    ) {
+        eprintln!("failed to add {ASSETS_DIR}: {err}");
+        return Err(());
+    }
+
+    let iso_opt = iso_opt()?;
+    // Read back to implant the checksums
+    let iso_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&iso);
+    let mut iso_file = match iso_file {
+        Ok(iso_file) => iso_file,
+        Err(err) => {
+            eprintln!("failed to create {iso}: {err}");
+            return Err(());
+        }
+    };
+    let report =
+        match iso_builder.write_to_with_checksums(&iso_opt, &mut iso_file) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("failed to create {iso}: {err}");
+                return Err(());
+            }
+        };
+    if report.deduplicated_size != 0 {
+        eprintln!(
+            "{iso}: {} bytes saved by deduplication",
+            report.deduplicated_size
+        );
+    }
+    drop(iso_file);
+
+    verify(iso.as_std_path())?;
     Ok(iso.into_std_path_buf())
 }
 
+/// Lint the produced image, so writer regressions don't go unnoticed
+fn verify(iso: &Path) -> Result<(), ()> {
+    let iso_file = match File::open(iso) {
+        Ok(iso_file) => iso_file,
+        Err(err) => {
+            eprintln!("failed to open {}: {err}", iso.display());
+            return Err(());
+        }
+    };
+
+    let mut diagnostics = iso::verify(BufReader::new(&iso_file));
+    diagnostics.extend(iso::verify_checksums(BufReader::new(&iso_file)));
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {diagnostic}", iso.display());
+    }
+
+    if diagnostics
+        .iter()
+        .any(|x| x.severity == iso::Severity::Error)
+    {
+        eprintln!("{} isn't a valid image", iso.display());
+        return Err(());
+    }
+
+    Ok(())
+}
+
 fn run(game: &Path, _opt: RunOpt) -> ExitCode {
     let dreambox_path = env::var_os("DREAMBOX_PATH")
         .expect("Missing DREAMBOX_PATH env variable");
@@ -112,20 +284,73 @@ fn run(game: &Path, _opt: RunOpt) -> ExitCode {
     }
 }
 
+fn extract(opt: ExtractOpt) -> ExitCode {
+    let res = File::open(&opt.iso)
+        .and_then(|iso_file| iso::read_iso(BufReader::new(iso_file)))
+        .and_then(|mut image| image.extract(&opt.dir));
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("failed to extract {}: {err}", opt.iso.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn append(opt: AppendOpt) -> ExitCode {
+    let mut files = Vec::new();
+    for file in &opt.files {
+        let Some((path, host_path)) = file.split_once('=') else {
+            eprintln!("invalid file \"{file}\", expected path=host_path");
+            return ExitCode::FAILURE;
+        };
+        files.push((path, host_path));
+    }
+    let Ok(iso_opt) = iso_opt() else {
+        return ExitCode::FAILURE;
+    };
+
+    let res = File::options()
+        .read(true)
+        .write(true)
+        .open(&opt.iso)
+        .map_err(iso::IsoError::Io)
+        .and_then(|mut iso_file| {
+            let mut iso_builder =
+                iso::IsoBuilder::from_image(BufReader::new(&iso_file))?;
+            for (path, host_path) in files {
+                iso_builder.add_host_file(path, host_path)?;
+            }
+            iso_builder.append_to(&iso_opt, &mut iso_file)
+        });
+    if let Err(err) = res {
+        eprintln!("failed to append to {}: {err}", opt.iso.display());
+        return ExitCode::FAILURE;
+    }
+
+    match verify(&opt.iso) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(()) => ExitCode::FAILURE,
+    }
+}
+
 fn main() -> ExitCode {
     let args = Opt::parse();
 
     match args {
-        Opt::Build(opt) => {
-            build(opt).unwrap();
-            ExitCode::SUCCESS
-        }
+        Opt::Build(opt) => match build(opt) {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(()) => ExitCode::FAILURE,
+        },
         Opt::Run(opt) => {
-            let game = build(BuildOpt {
+            let Ok(game) = build(BuildOpt {
                 release: opt.release,
-            })
-            .unwrap();
+            }) else {
+                return ExitCode::FAILURE;
+            };
             run(&game, opt)
         }
+        Opt::Extract(opt) => extract(opt),
+        Opt::Append(opt) => append(opt),
     }
 }
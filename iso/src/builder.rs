@@ -0,0 +1,398 @@
+use crate::WriteReport;
+use crate::content::{ContentProvider, ReaderFn};
+use crate::directory_entry::DirectoryEntry;
+use crate::error::{IsoError, Result};
+use crate::file_entry::{FileEntry, FileType, ReadProvider};
+use crate::option;
+use crate::rock_ridge::PosixAttributes;
+use crate::session;
+
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn split_path(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut components: Vec<&str> =
+        path.split('/').filter(|x| !x.is_empty()).collect();
+
+    match components.pop() {
+        Some(name) if name != "." && name != ".." => Ok((components, name)),
+        _ => Err(IsoError::InvalidPath(path.to_string())),
+    }
+}
+
+/// Build an image from files placed at arbitrary paths.
+///
+/// Parent directories are created as needed.
+#[derive(Debug)]
+pub struct IsoBuilder {
+    tree: DirectoryEntry,
+    /// Holds the files of an existing image
+    imported: bool,
+}
+
+impl Default for IsoBuilder {
+    fn default() -> Self {
+        IsoBuilder::new()
+    }
+}
+
+impl IsoBuilder {
+    pub fn new() -> IsoBuilder {
+        IsoBuilder {
+            tree: DirectoryEntry::new().unwrap(),
+            imported: false,
+        }
+    }
+
+    /// Start from the files of the last session of `image`, to append a new
+    /// session to it with [IsoBuilder::append_to]. The imported files keep
+    /// their content where it is, files added at their paths replace them.
+    pub fn from_image<R>(image: R) -> Result<IsoBuilder>
+    where
+        R: Read + Seek,
+    {
+        let mut image = crate::read_iso(image)?;
+        Ok(IsoBuilder {
+            tree: session::import_tree(&mut image)?,
+            imported: true,
+        })
+    }
+
+    fn get_directory(
+        &mut self,
+        components: &[&str],
+        create: bool,
+    ) -> Option<&mut DirectoryEntry> {
+        let mut directory = &mut self.tree;
+
+        for name in components {
+            if directory.get_directory(name).is_none() {
+                if !create || directory.has_file(name) {
+                    return None;
+                }
+
+                let mut new_dir = DirectoryEntry::new().unwrap();
+                new_dir.name = name.to_string();
+                directory.dir_childs.push(new_dir);
+            }
+
+            directory = directory.get_directory(name).unwrap();
+        }
+
+        Some(directory)
+    }
+
+    fn add_entry(&mut self, path: &str, mut file: FileEntry) -> Result<()> {
+        let (components, name) = split_path(path)?;
+
+        let directory = self
+            .get_directory(&components, true)
+            .ok_or_else(|| IsoError::NotADirectory(path.to_string()))?;
+
+        if directory.get_directory(name).is_some()
+            || directory
+                .files_childs
+                .iter()
+                .any(|x| x.get_file_name() == name && !x.is_imported())
+        {
+            return Err(IsoError::AlreadyExists(path.to_string()));
+        }
+
+        // Files of a previous session are updated
+        directory.files_childs.retain(|x| x.get_file_name() != name);
+        file.set_file_name(name.to_string());
+        directory.add_file(file);
+
+        Ok(())
+    }
+
+    /// Add a file at `path` with the given content
+    pub fn add_buffer(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        let size = data.len();
+        let file_type = FileType::Buffer {
+            name: String::new(),
+            data,
+        };
+
+        self.add_entry(path, FileEntry::new(file_type, size))
+    }
+
+    /// Add a file at `path` whose content of `size` bytes is read from the
+    /// reader returned by `open`, once the image is being written
+    pub fn add_reader<F>(
+        &mut self,
+        path: &str,
+        size: usize,
+        open: F,
+    ) -> Result<()>
+    where
+        F: Fn() -> io::Result<Box<dyn Read>> + Send + Sync + 'static,
+    {
+        let provider = ReaderFn {
+            size: size.try_into().unwrap(),
+            open,
+        };
+
+        self.add_provider(path, provider)
+    }
+
+    /// Add a file at `path` whose content is read from `provider`, once the
+    /// image is being written
+    pub fn add_provider<P>(&mut self, path: &str, provider: P) -> Result<()>
+    where
+        P: ContentProvider + 'static,
+    {
+        let size = provider.size().try_into().unwrap();
+        let file_type = FileType::Provider {
+            name: String::new(),
+            provider: ReadProvider(Arc::new(provider)),
+        };
+
+        self.add_entry(path, FileEntry::new(file_type, size))
+    }
+
+    /// Add the host file `host_path` at `path`
+    pub fn add_host_file<P>(&mut self, path: &str, host_path: P) -> Result<()>
+    where
+        P: Into<PathBuf>,
+    {
+        let host_path: PathBuf = host_path.into();
+        let metadata = host_path
+            .metadata()
+            .map_err(|err| IsoError::host_io(&host_path, err))?;
+        let file_type = FileType::Regular {
+            name: String::new(),
+            path: host_path,
+        };
+
+        let mut file =
+            FileEntry::new(file_type, metadata.len().try_into().unwrap());
+        file.posix = PosixAttributes::from_metadata(&metadata);
+
+        self.add_entry(path, file)
+    }
+
+    /// Add a Rock Ridge symbolic link to `target` at `path`
+    pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<()> {
+        let file_type = FileType::Symlink {
+            name: String::new(),
+            target: target.to_string(),
+        };
+
+        self.add_entry(path, FileEntry::new(file_type, 0))
+    }
+
+    /// Mirror the content of the host directory `host_path` into the
+    /// directory at `path`, merging it with the existing entries
+    pub fn add_host_directory<P>(
+        &mut self,
+        path: &str,
+        host_path: P,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.add_filtered_host_directory(
+            path,
+            host_path,
+            &option::HostFilter::default(),
+        )
+    }
+
+    /// Same as [IsoBuilder::add_host_directory], only keeping the entries
+    /// selected by `filter`
+    pub fn add_filtered_host_directory<P>(
+        &mut self,
+        path: &str,
+        host_path: P,
+        filter: &option::HostFilter,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut mirrored = DirectoryEntry::new()?;
+        mirrored.set_path(&[host_path.as_ref().to_path_buf()], filter)?;
+
+        let components: Vec<&str> =
+            path.split('/').filter(|x| !x.is_empty()).collect();
+        let directory = self
+            .get_directory(&components, true)
+            .ok_or_else(|| IsoError::NotADirectory(path.to_string()))?;
+
+        directory.merge(mirrored);
+
+        Ok(())
+    }
+
+    /// Create an empty directory at `path`, as well as its parents
+    pub fn add_directory(&mut self, path: &str) -> Result<()> {
+        let components: Vec<&str> =
+            path.split('/').filter(|x| !x.is_empty()).collect();
+
+        self.get_directory(&components, true)
+            .map(|_| ())
+            .ok_or_else(|| IsoError::NotADirectory(path.to_string()))
+    }
+
+    /// Move the file or directory at `from` to `to`
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let (from_components, from_name) = split_path(from)?;
+        let (to_components, to_name) = split_path(to)?;
+
+        // A directory can't be moved inside of itself
+        let mut from_path = from_components.clone();
+        from_path.push(from_name);
+        if to_components.starts_with(&from_path) {
+            return Err(IsoError::InvalidPath(to.to_string()));
+        }
+
+        let from_directory = self
+            .get_directory(&from_components, false)
+            .ok_or_else(|| IsoError::NotFound(from.to_string()))?;
+        let is_file = from_directory.has_file(from_name);
+        if !is_file && from_directory.get_directory(from_name).is_none() {
+            return Err(IsoError::NotFound(from.to_string()));
+        }
+
+        // The missing parents of `to` are only created once every check
+        // passed, a failed rename leaves the tree as it was
+        let existing_len = (0..=to_components.len())
+            .rev()
+            .find(|&len| {
+                self.get_directory(&to_components[..len], false).is_some()
+            })
+            .unwrap();
+        let existing = self
+            .get_directory(&to_components[..existing_len], false)
+            .unwrap();
+        match to_components.get(existing_len) {
+            Some(missing) if existing.has_file(missing) => {
+                return Err(IsoError::NotADirectory(to.to_string()));
+            }
+            Some(_) => {}
+            None => {
+                if existing.has_file(to_name)
+                    || existing.get_directory(to_name).is_some()
+                {
+                    return Err(IsoError::AlreadyExists(to.to_string()));
+                }
+            }
+        }
+
+        let from_directory =
+            self.get_directory(&from_components, false).unwrap();
+        if is_file {
+            let index = from_directory
+                .files_childs
+                .iter()
+                .position(|x| x.get_file_name() == from_name)
+                .unwrap();
+            let mut file = from_directory.files_childs.remove(index);
+            file.set_file_name(to_name.to_string());
+
+            let to_directory =
+                self.get_directory(&to_components, true).unwrap();
+            to_directory.add_file(file);
+        } else {
+            let index = from_directory
+                .dir_childs
+                .iter()
+                .position(|x| x.name == from_name)
+                .unwrap();
+            let mut directory = from_directory.dir_childs.remove(index);
+            directory.name = to_name.to_string();
+
+            let to_directory =
+                self.get_directory(&to_components, true).unwrap();
+            to_directory.dir_childs.push(directory);
+        }
+
+        Ok(())
+    }
+
+    /// Write the image to `out`, the files of `opt.input_files` are mirrored
+    /// at the root along with the ones added to the builder. Implanting the
+    /// digests of `opt.checksums` reads the image back, which needs
+    /// [IsoBuilder::write_to_with_checksums].
+    pub fn write_to<W>(
+        self,
+        opt: &option::Opt,
+        out: &mut W,
+    ) -> Result<WriteReport>
+    where
+        W: Write + Seek,
+    {
+        if opt.checksums {
+            return Err(IsoError::ChecksumsNeedReadableOutput);
+        }
+
+        self.write_first_session(opt, out)
+    }
+
+    /// Same as [IsoBuilder::write_to], the image is then read back to implant
+    /// its digests when `opt.checksums` is set
+    pub fn write_to_with_checksums<F>(
+        self,
+        opt: &option::Opt,
+        out: &mut F,
+    ) -> Result<WriteReport>
+    where
+        F: Read + Write + Seek,
+    {
+        let report = self.write_first_session(opt, out)?;
+        if opt.checksums {
+            crate::implant_checksums(out)?;
+        }
+
+        Ok(report)
+    }
+
+    fn write_first_session<W>(
+        self,
+        opt: &option::Opt,
+        out: &mut W,
+    ) -> Result<WriteReport>
+    where
+        W: Write + Seek,
+    {
+        if self.imported {
+            return Err(IsoError::ImportedFiles);
+        }
+
+        crate::write_iso(self.tree, opt, 0, out)
+    }
+
+    /// Append the files as a new session at the end of `image`, the one given
+    /// to [IsoBuilder::from_image], without rewriting the content of the
+    /// previous sessions. The volume descriptors of the new session are then
+    /// copied at the start of the image, where readers look for them in image
+    /// files and on rewritable media.
+    pub fn append_to<F>(
+        self,
+        opt: &option::Opt,
+        image: &mut F,
+    ) -> Result<WriteReport>
+    where
+        F: Read + Write + Seek,
+    {
+        session::check_options(opt)?;
+        let session_lba = session::get_next_session_lba(image)?;
+
+        let report = crate::write_iso(self.tree, opt, session_lba, image)?;
+        session::copy_volume_descriptors(image, session_lba)?;
+        if opt.checksums {
+            crate::implant_checksums(image)?;
+        }
+
+        Ok(report)
+    }
+
+    pub fn build(self, opt: &option::Opt) -> Result<Vec<u8>> {
+        let mut out = io::Cursor::new(Vec::new());
+        self.write_to_with_checksums(opt, &mut out)?;
+
+        Ok(out.into_inner())
+    }
+}
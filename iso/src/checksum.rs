@@ -0,0 +1,343 @@
+use crate::directory_entry::DirectoryEntry;
+use crate::error::Result;
+use crate::file_entry::{FileEntry, FileType};
+use crate::utils::{LOGIC_SIZE, LOGIC_SIZE_U32};
+use crate::verify::{Diagnostic, Severity};
+
+use byteorder::{ByteOrder, LittleEndian};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::io;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::ops::Range;
+
+/// Name of the manifest of the file contents at the root of the image, in the
+/// format of `sha256sum`
+pub const MANIFEST_NAME: &str = "SHA256SUMS";
+
+/// Application use area of the primary volume descriptor, where the image
+/// digests are implanted
+const APPLICATION_USE_OFFSET: usize = 0x373;
+const APPLICATION_USE_SIZE: usize = 0x200;
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// Reader updating a SHA-256 digest with everything read through it
+pub struct DigestReader<'a, R> {
+    pub inner: R,
+    pub digest: &'a mut Sha256,
+}
+
+impl<R> Read for DigestReader<'_, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.digest.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Files having a content, along with their path as seen by Rock Ridge
+/// readers: relocated directories are at their original place
+fn collect_visible_files<'a>(
+    directory: &'a DirectoryEntry,
+    relocated: &HashMap<u32, &'a DirectoryEntry>,
+    path: &str,
+    res: &mut Vec<(String, &'a FileEntry)>,
+) {
+    for file in &directory.files_childs {
+        if file.has_content() {
+            res.push((format!("{path}{}", file.get_file_name()), file));
+        }
+    }
+
+    // Relocated directories are reached through their placeholders
+    for child in &directory.dir_childs {
+        if child.relocation.is_none() {
+            let path = format!("{path}{}/", child.name);
+            collect_visible_files(child, relocated, &path, res);
+        }
+    }
+
+    for file in &directory.files_childs {
+        if let FileType::Relocated { id, .. } = file.file_type {
+            let path = format!("{path}{}/", file.get_file_name());
+            collect_visible_files(relocated[&id], relocated, &path, res);
+        }
+    }
+}
+
+/// Content of the manifest, with the digests computed while writing the
+/// files. The digests of the files not written yet are zeros, which gives
+/// the size of the manifest beforehand.
+pub fn get_manifest(tree: &DirectoryEntry) -> Vec<u8> {
+    let mut relocated = HashMap::new();
+    tree.collect_relocated_directories(&mut relocated);
+    let mut files = Vec::new();
+    collect_visible_files(tree, &relocated, "", &mut files);
+
+    // Duplicates share the extent, and the digest, of the file writing it
+    let digests: HashMap<u32, [u8; 32]> = files
+        .iter()
+        .filter_map(|(_, file)| Some((file.lba, file.sha256?)))
+        .collect();
+
+    let mut res = Vec::new();
+    for (path, file) in files {
+        if path == MANIFEST_NAME {
+            continue;
+        }
+
+        let digest = digests.get(&file.lba).copied().unwrap_or_default();
+        res.extend_from_slice(
+            format!("{}  {path}\n", to_hex(&digest)).as_bytes(),
+        );
+    }
+
+    res
+}
+
+/// Offset of the primary volume descriptor, along with its content and the
+/// size of the volume in bytes
+pub fn read_primary<R>(image: &mut R) -> io::Result<(u64, Vec<u8>, u64)>
+where
+    R: Read + Seek,
+{
+    let mut offset = 0x10 * u64::from(LOGIC_SIZE_U32);
+    loop {
+        let mut buff = vec![0; LOGIC_SIZE];
+        image.seek(SeekFrom::Start(offset))?;
+        image.read_exact(&mut buff)?;
+
+        if &buff[1..6] != b"CD001" || buff[0] == 0xFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "primary volume descriptor not found",
+            ));
+        }
+        if buff[0] == 0x1 {
+            let volume_size = u64::from(LittleEndian::read_u32(&buff[80..]))
+                * u64::from(LOGIC_SIZE_U32);
+            return Ok((offset, buff, volume_size));
+        }
+
+        offset += u64::from(LOGIC_SIZE_U32);
+    }
+}
+
+/// MD5 and SHA-256 digests of the first `size` bytes of the image, with the
+/// implanted digests replaced by spaces like implantisomd5 does
+fn get_image_digests<R>(
+    image: &mut R,
+    implanted: Range<u64>,
+    size: u64,
+) -> io::Result<([u8; 16], [u8; 32])>
+where
+    R: Read + Seek,
+{
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+
+    image.seek(SeekFrom::Start(0))?;
+    let mut buff = vec![0; LOGIC_SIZE * 0x10];
+    let mut offset = 0;
+    while offset < size {
+        let len = usize::try_from(size - offset)
+            .unwrap_or(usize::MAX)
+            .min(buff.len());
+        image.read_exact(&mut buff[..len])?;
+
+        let end = offset + u64::try_from(len).unwrap();
+        let start_blank = implanted.start.clamp(offset, end) - offset;
+        let end_blank = implanted.end.clamp(offset, end) - offset;
+        buff[usize::try_from(start_blank).unwrap()
+            ..usize::try_from(end_blank).unwrap()]
+            .fill(b' ');
+
+        md5.update(&buff[..len]);
+        sha256.update(&buff[..len]);
+        offset = end;
+    }
+
+    Ok((md5.finalize().into(), sha256.finalize().into()))
+}
+
+/// Location of the application use area of the primary volume descriptor at
+/// `offset`
+fn get_implanted_range(offset: u64) -> Range<u64> {
+    let start = offset + u64::try_from(APPLICATION_USE_OFFSET).unwrap();
+    start..start + u64::try_from(APPLICATION_USE_SIZE).unwrap()
+}
+
+/// Implant the MD5 and SHA-256 digests of the whole volume in the application
+/// use area of the primary volume descriptor, in the format of implantisomd5
+/// so `checkisomd5` can check the image. Called when `Opt::checksums` is set
+/// by `IsoBuilder::write_to_with_checksums`, `IsoBuilder::append_to` and the
+/// functions built on them, `write_to` refuses the option as it can't read
+/// the image back.
+pub fn implant_checksums<F>(image: &mut F) -> Result<()>
+where
+    F: Read + Write + Seek,
+{
+    let (offset, _, volume_size) = read_primary(image)?;
+    let implanted = get_implanted_range(offset);
+    let (md5, sha256) =
+        get_image_digests(image, implanted.clone(), volume_size)?;
+
+    let tag = format!(
+        "ISO MD5SUM = {};SKIPSECTORS = 0;RHLISOSTATUS=0;ISO SHA256SUM = {};",
+        to_hex(&md5),
+        to_hex(&sha256)
+    );
+    let mut area = [b' '; APPLICATION_USE_SIZE];
+    area[..tag.len()].copy_from_slice(tag.as_bytes());
+
+    image.seek(SeekFrom::Start(implanted.start))?;
+    image.write_all(&area)?;
+
+    Ok(())
+}
+
+/// Value of a `NAME = value;` field of the implanted digests
+fn get_tag_field<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    tag.split(';')
+        .find_map(|x| x.strip_prefix(name)?.strip_prefix(" = "))
+        .map(str::trim)
+}
+
+fn check_image_digests<R>(
+    image: &mut R,
+    res: &mut Vec<Diagnostic>,
+) -> io::Result<()>
+where
+    R: Read + Seek,
+{
+    let (offset, primary, volume_size) = read_primary(image)?;
+    let lba = u32::try_from(offset / u64::from(LOGIC_SIZE_U32)).unwrap();
+
+    let tag = String::from_utf8_lossy(
+        &primary[APPLICATION_USE_OFFSET..][..APPLICATION_USE_SIZE],
+    );
+    let Some(md5) = get_tag_field(&tag, "ISO MD5SUM") else {
+        res.push(Diagnostic {
+            severity: Severity::Warning,
+            lba: Some(lba),
+            message: "no implanted image digest".to_string(),
+        });
+        return Ok(());
+    };
+
+    // Some of the last blocks may be left out, like padding added by burning
+    let skipped_size = get_tag_field(&tag, "SKIPSECTORS")
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or_default()
+        .saturating_mul(u64::from(LOGIC_SIZE_U32));
+    let (image_md5, image_sha256) = get_image_digests(
+        image,
+        get_implanted_range(offset),
+        volume_size.saturating_sub(skipped_size),
+    )?;
+
+    if !md5.eq_ignore_ascii_case(&to_hex(&image_md5)) {
+        res.push(Diagnostic {
+            severity: Severity::Error,
+            lba: Some(lba),
+            message: "image MD5 digest doesn't match the implanted one"
+                .to_string(),
+        });
+    }
+    if let Some(sha256) = get_tag_field(&tag, "ISO SHA256SUM")
+        && !sha256.eq_ignore_ascii_case(&to_hex(&image_sha256))
+    {
+        res.push(Diagnostic {
+            severity: Severity::Error,
+            lba: Some(lba),
+            message: "image SHA-256 digest doesn't match the implanted one"
+                .to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn check_manifest<R>(image: R, res: &mut Vec<Diagnostic>) -> io::Result<()>
+where
+    R: Read + Seek,
+{
+    let mut image = crate::read_iso(image)?;
+    let Some(manifest) = image.root.get_file(MANIFEST_NAME).cloned() else {
+        res.push(Diagnostic {
+            severity: Severity::Warning,
+            lba: None,
+            message: format!("no {MANIFEST_NAME} manifest"),
+        });
+        return Ok(());
+    };
+    let manifest_lba = manifest.extents[0].0;
+
+    let mut content = String::new();
+    image.open_file(&manifest).read_to_string(&mut content)?;
+    for line in content.lines() {
+        let Some((digest, path)) = line.split_once("  ") else {
+            res.push(Diagnostic {
+                severity: Severity::Error,
+                lba: Some(manifest_lba),
+                message: format!("invalid {MANIFEST_NAME} line {line:?}"),
+            });
+            continue;
+        };
+
+        let Some(file) = image.root.get_file(path).cloned() else {
+            res.push(Diagnostic {
+                severity: Severity::Error,
+                lba: Some(manifest_lba),
+                message: format!(
+                    "{path}: listed in {MANIFEST_NAME} but missing"
+                ),
+            });
+            continue;
+        };
+
+        let mut file_digest = Sha256::new();
+        io::copy(&mut image.open_file(&file), &mut file_digest)?;
+        if !digest.eq_ignore_ascii_case(&to_hex(&file_digest.finalize())) {
+            res.push(Diagnostic {
+                severity: Severity::Error,
+                lba: Some(file.extents[0].0),
+                message: format!(
+                    "{path}: SHA-256 digest doesn't match {MANIFEST_NAME}"
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the image against the digests implanted by [implant_checksums] and
+/// the files against the manifest, when they are present
+pub fn verify_checksums<R>(mut image: R) -> Vec<Diagnostic>
+where
+    R: Read + Seek,
+{
+    let mut res = Vec::new();
+    let checked = check_image_digests(&mut image, &mut res)
+        .and_then(|()| check_manifest(image, &mut res));
+
+    if let Err(err) = checked {
+        res.push(Diagnostic {
+            severity: Severity::Error,
+            lba: None,
+            message: format!("I/O error: {err}"),
+        });
+    }
+
+    res
+}
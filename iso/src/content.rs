@@ -0,0 +1,55 @@
+use std::io;
+use std::io::prelude::*;
+
+/// Source of the content of a file of the image, for generated, compressed
+/// or lazily fetched data.
+///
+/// The content is opened every time it is read while writing the image: once
+/// for most files, a few more times for the deduplicated and the compressed
+/// ones.
+pub trait ContentProvider: Send + Sync {
+    /// Size of the content in bytes, the readers must give exactly as many
+    fn size(&self) -> u64;
+
+    /// Open a new reader over the content, from its start
+    fn open(&self) -> io::Result<Box<dyn Read + '_>>;
+}
+
+impl ContentProvider for Vec<u8> {
+    fn size(&self) -> u64 {
+        u64::try_from(self.len()).unwrap()
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(&self[..]))
+    }
+}
+
+impl ContentProvider for &'static [u8] {
+    fn size(&self) -> u64 {
+        u64::try_from(self.len()).unwrap()
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(*self))
+    }
+}
+
+/// Content of a known size read from the readers returned by a closure
+pub struct ReaderFn<F> {
+    pub size: u64,
+    pub open: F,
+}
+
+impl<F> ContentProvider for ReaderFn<F>
+where
+    F: Fn() -> io::Result<Box<dyn Read>> + Send + Sync,
+{
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        (self.open)()
+    }
+}
@@ -1,9 +1,15 @@
-use crate::file_entry::{FileEntry, FileType};
+use crate::error::{IsoError, Result};
+use crate::file_entry::{FileEntry, FileType, MAX_EXTENT_SIZE};
+use crate::filter::{self, IgnoreRules};
+use crate::option::{DotFiles, HostFilter, InterchangeLevel};
+use crate::rock_ridge::{self, ContinuationAreas, PosixAttributes};
 use crate::utils;
-use crate::utils::{LOGIC_SIZE, LOGIC_SIZE_I64, LOGIC_SIZE_U32};
+use crate::utils::{Hierarchy, LOGIC_SIZE, LOGIC_SIZE_U32};
+use crate::utils::{JolietNameMapper, NameMapper};
 use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
 use chrono::prelude::*;
 
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::fs::DirEntry;
 use std::fs::Metadata;
@@ -12,15 +18,96 @@ use std::io::SeekFrom;
 use std::io::prelude::*;
 use std::path::PathBuf;
 
+/// Deepest directory level allowed by ECMA-119 6.8.2.1, the root being the
+/// first one
+pub const MAX_DIRECTORY_LEVEL: usize = 8;
+
+/// Names of the directory holding the relocated directories, the only ones
+/// libarchive recognizes
+const RELOCATION_DIRECTORY_NAMES: [&str; 2] = ["rr_moved", ".rr_moved"];
+
+/// Rock Ridge relocation of a directory nested too deep (IEEE P1282 4.1.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// Identifies the placeholder left in the original parent
+    pub id: u32,
+    /// Location of the original parent
+    pub parent_lba: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct DirectoryEntry {
+    /// Number in the primary path table, the root being 1
     pub path_table_index: u32,
-    pub parent_index: u32,
-    pub path: PathBuf,
+    pub name: String,
+    /// ISO 9660 identifier, unique in the parent directory
+    pub identifier: Vec<u8>,
+    /// Joliet identifier, unique in the parent directory
+    pub joliet_identifier: Vec<u8>,
+    /// Mirrored host directory, if any
+    pub host_path: Option<PathBuf>,
     pub dir_childs: Vec<DirectoryEntry>,
     pub files_childs: Vec<FileEntry>,
-    pub continuation_area: Option<Vec<u8>>,
     pub lba: u32,
+    pub joliet_lba: u32,
+    /// Location of the continuation areas of the records, if any
+    pub continuation_lba: u32,
+    pub timestamp: DateTime<Utc>,
+    pub posix: PosixAttributes,
+    /// Rock Ridge file serial number, unique in the image
+    pub serial: u32,
+    /// Set on the directories moved to the relocation directory
+    pub relocation: Option<Relocation>,
+    /// Created to hold the relocated directories, which Joliet records at
+    /// their original place instead
+    pub relocation_directory: bool,
+    /// Recorded with the hidden flag
+    pub hidden: bool,
+}
+
+/// Record of a directory extent, besides '.' and '..'
+#[derive(Clone, Copy)]
+enum Record<'a> {
+    Directory(&'a DirectoryEntry),
+    File(&'a FileEntry),
+}
+
+impl<'a> Record<'a> {
+    /// Directory described by the record in the given hierarchy, Joliet
+    /// recording the relocated directories in place of their placeholder
+    fn get_directory(
+        self,
+        hierarchy: Hierarchy,
+        relocated: &HashMap<u32, &'a DirectoryEntry>,
+    ) -> Option<&'a DirectoryEntry> {
+        match self {
+            Record::Directory(entry) => Some(entry),
+            Record::File(FileEntry {
+                file_type: FileType::Relocated { id, .. },
+                ..
+            }) if hierarchy == Hierarchy::Joliet => Some(relocated[id]),
+            Record::File(_) => None,
+        }
+    }
+
+    fn get_identifier(&self, hierarchy: Hierarchy) -> &[u8] {
+        match (self, hierarchy) {
+            (Record::Directory(entry), Hierarchy::Primary) => &entry.identifier,
+            (Record::Directory(entry), Hierarchy::Joliet) => {
+                &entry.joliet_identifier
+            }
+            (Record::File(entry), Hierarchy::Primary) => &entry.identifier,
+            (Record::File(entry), Hierarchy::Joliet) => {
+                &entry.joliet_identifier
+            }
+        }
+    }
+}
+
+/// Whether the host entry at `path` is hidden as a dot file
+fn is_dot_file_hidden(filter: &HostFilter, path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    filter.dot_files == DotFiles::Hide && name.starts_with('.')
 }
 
 impl DirectoryEntry {
@@ -28,35 +115,32 @@ impl DirectoryEntry {
         directory_entry: &DirectoryEntry,
         output_writter: &mut T,
         directory_type: u32,
+        hierarchy: Hierarchy,
+        continuation_areas: &mut ContinuationAreas,
+        parent_link: Option<u32>,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
-        let current_pos: i32 =
-            output_writter.stream_position()?.try_into().unwrap();
-        let expected_aligned_pos = utils::align_up_i32(
-            current_pos,
-            LOGIC_SIZE_U32.try_into().unwrap(),
-        );
+        let current_pos = output_writter.stream_position()?;
+        let expected_aligned_pos =
+            current_pos.next_multiple_of(LOGIC_SIZE_U32.into());
 
         let diff_size = expected_aligned_pos - current_pos;
-        let file_entry_size: i32 = directory_entry
-            .get_entry_size(Some(directory_type))
-            .try_into()
-            .unwrap();
+        let file_entry_size = directory_entry.get_record_size(
+            directory_type,
+            hierarchy,
+            parent_link,
+        );
 
-        if file_entry_size > diff_size && diff_size != 0 {
+        if u64::from(file_entry_size) > diff_size && diff_size != 0 {
             let padding: Vec<u8> = vec![0; diff_size.try_into().unwrap()];
             output_writter.write_all(&padding)?;
         }
 
-        let old_pos: i32 =
-            output_writter.stream_position()?.try_into().unwrap();
-
-        let file_name =
-            directory_entry.path.file_name().unwrap().to_str().unwrap();
+        let old_pos = output_writter.stream_position()?;
 
-        let file_name_fixed = utils::convert_name(file_name);
+        let file_name_fixed = directory_entry.get_identifier(hierarchy);
         let file_identifier = match directory_type {
             1 => &[0u8],
             2 => &[1u8],
@@ -75,28 +159,23 @@ impl DirectoryEntry {
 
         // Location of extent (in LB)
         write_bothendian! {
-            output_writter.write_u32(directory_entry.lba)?;
+            output_writter.write_u32(directory_entry.get_lba(hierarchy))?;
         }
 
         // Extent size (size of an LB)
         write_bothendian! {
-            output_writter.write_u32(directory_entry.get_extent_size_in_lb() * LOGIC_SIZE_U32)?;
+            output_writter.write_u32(directory_entry.get_extent_size_in_lb(hierarchy) * LOGIC_SIZE_U32)?;
         }
 
-        let record_datetime: DateTime<Utc> = Utc::now();
-        output_writter
-            .write_u8((record_datetime.year() - 1900).try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.month().try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.day().try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.hour().try_into().unwrap())?;
-        output_writter
-            .write_u8(record_datetime.minute().try_into().unwrap())?;
-        output_writter
-            .write_u8(record_datetime.second().try_into().unwrap())?;
-        output_writter.write_u8(0u8)?;
+        utils::write_record_datetime(
+            output_writter,
+            &directory_entry.timestamp,
+        )?;
 
-        // file flags (0x2 == directory)
-        output_writter.write_u8(0x2u8)?;
+        // file flags (0x2 == directory, 0x1 == hidden), only the record in
+        // the parent is hidden
+        let hidden = directory_type == 0 && directory_entry.hidden;
+        output_writter.write_u8(if hidden { 0x3 } else { 0x2 })?;
 
         output_writter.write_u8(0x0u8)?;
         output_writter.write_u8(0x0u8)?;
@@ -113,172 +192,274 @@ impl DirectoryEntry {
             output_writter.write_u8(0x0u8)?;
         }
 
+        let (mut system_use, continuation) = directory_entry
+            .get_record_system_use(directory_type, hierarchy, parent_link)?;
+        if !continuation.is_empty() {
+            system_use.extend(continuation_areas.push(&continuation)?);
+        }
+        // Records have an even length
+        if !system_use.len().is_multiple_of(2) {
+            system_use.push(0);
+        }
+        output_writter.write_all(&system_use)?;
+
+        let new_pos = output_writter.stream_position()?;
+
+        assert!(old_pos + u64::from(file_entry_size) == new_pos);
+
+        Ok(())
+    }
+
+    /// SUSP and Rock Ridge entries of the record, `parent_link` being the
+    /// original parent of a relocated directory for its '..' record
+    fn get_system_use(
+        &self,
+        directory_type: u32,
+        hierarchy: Hierarchy,
+        parent_link: Option<u32>,
+    ) -> std::io::Result<Vec<u8>> {
+        let mut res = Vec::new();
+
+        // Joliet hierarchy doesn't carry any SUSP/Rock Ridge entries, neither
+        // do path tables and the volume descriptor root record
+        if hierarchy == Hierarchy::Joliet || directory_type >= 5 {
+            return Ok(res);
+        }
+
         // SUSP entries for root '.'
         if directory_type == 3 {
-            // SUSP 'SP' entry (IEEE P1281 5.3)
-            output_writter.write_all(b"SP")?;
-            output_writter.write_u8(0x7)?;
-            output_writter.write_u8(0x1)?;
-            output_writter.write_u8(0xBE)?;
-            output_writter.write_u8(0xEF)?;
-            output_writter.write_u8(0x0)?;
-
-            // TODO: 'CE' can be found in other entries, move this when size calculation will be good
-            if directory_entry.continuation_area.is_some() {
-                // SUSP 'CE' entry (IEEE P1281 5.1)
-                output_writter.write_all(b"CE")?;
-                output_writter.write_u8(0x1c)?;
-                output_writter.write_u8(0x1)?;
-
-                // The 'CE' logical block is just after the root directory entries
-                write_bothendian! {
-                    output_writter.write_u32(directory_entry.lba + directory_entry.get_extent_size_in_lb())?;
-                }
+            rock_ridge::write_sp(&mut res)?;
+        }
 
-                // The 'CE' offset inside the block (0 for our usecase)
-                write_bothendian! {
-                    output_writter.write_u32(0)?;
-                }
+        rock_ridge::write_px(
+            &mut res,
+            &self.posix,
+            self.get_link_count(),
+            self.serial,
+        )?;
+        rock_ridge::write_tf(&mut res, &self.timestamp)?;
 
-                // 'CE' section size
-                match &directory_entry.continuation_area {
-                    Some(continuation_area) => {
-                        write_bothendian! {
-                            output_writter.write_u32(continuation_area.len().try_into().unwrap())?;
-                        }
-                    }
-                    _ => panic!(),
-                }
+        if directory_type == 0 {
+            rock_ridge::write_nm(&mut res, &self.name)?;
+
+            if self.relocation.is_some() {
+                rock_ridge::write_re(&mut res)?;
             }
         }
 
-        if directory_type < 5 {
-            // RRIP 'PX' entry (IEEE P1282 4.1.1)
-            output_writter.write_all(b"PX")?;
-            output_writter.write_u8(0x2c)?;
-            output_writter.write_u8(0x1)?;
+        if let Some(lba) = parent_link {
+            rock_ridge::write_pl(&mut res, lba)?;
+        }
 
-            // file mode
-            write_bothendian! {
-                output_writter.write_u32(0o040_755)?; // harcoded drwxr-xr-x
-            }
+        // Rock Ridge 1.2 extension reference, only in the root '.' record
+        if directory_type == 3 {
+            rock_ridge::write_er(&mut res)?;
+        }
 
-            // links
-            write_bothendian! {
-                output_writter.write_u32(0x1)?; // one link
-            }
+        Ok(res)
+    }
 
-            // user id
-            write_bothendian! {
-                output_writter.write_u32(0x0)?; // root
-            }
+    /// SUSP entries kept in the record, without the 'CE' entry, and the ones
+    /// moved to its continuation area
+    fn get_record_system_use(
+        &self,
+        directory_type: u32,
+        hierarchy: Hierarchy,
+        parent_link: Option<u32>,
+    ) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+        let base_size = utils::get_entry_size(
+            0x21,
+            &self.get_identifier(hierarchy),
+            directory_type,
+            1,
+            0,
+        );
+        let available = 0xFF - usize::try_from(base_size).unwrap();
 
-            // group id
-            write_bothendian! {
-                output_writter.write_u32(0x0)?; // root
-            }
+        Ok(rock_ridge::split_system_use(
+            &self.get_system_use(directory_type, hierarchy, parent_link)?,
+            available,
+        ))
+    }
 
-            // "File Serial number"
-            write_bothendian! {
-                // dirty way to generate an inode but I guess it's fine
-                output_writter.write_u32(directory_entry.lba + directory_entry.path_table_index)?;
-            }
-        }
+    /// Location of the original parent of a relocated directory, recorded in
+    /// its '..' record
+    pub fn get_parent_link(&self) -> Option<u32> {
+        self.relocation.map(|relocation| relocation.parent_lba)
+    }
 
-        // RRIP 'NM' entry (IEEE P1282 4.1.4)
-        if directory_type == 0 {
-            output_writter.write_all(b"NM")?;
-            output_writter
-                .write_u8((0x5 + file_name.len()).try_into().unwrap())?;
-            output_writter.write_u8(0x1)?;
-            output_writter.write_u8(0x0)?; // No flags
-            output_writter.write_all(file_name.as_bytes())?;
+    /// Number of hard links to the directory: its entry in the parent, its
+    /// '.' entry and the '..' entries of its subdirectories
+    pub fn get_link_count(&self) -> u32 {
+        2 + u32::try_from(self.dir_childs.len()).unwrap()
+    }
+
+    /// Whether the directory has a record in its parent in the given
+    /// hierarchy, Joliet only knowing the original place of the relocated
+    /// directories
+    pub fn is_recorded(&self, hierarchy: Hierarchy) -> bool {
+        hierarchy == Hierarchy::Primary
+            || (self.relocation.is_none() && !self.relocation_directory)
+    }
+
+    /// Relocated directories of the tree, by relocation identifier
+    pub fn collect_relocated_directories<'a>(
+        &'a self,
+        res: &mut HashMap<u32, &'a DirectoryEntry>,
+    ) {
+        if let Some(relocation) = &self.relocation {
+            res.insert(relocation.id, self);
         }
 
-        let new_pos: i32 =
-            output_writter.stream_position()?.try_into().unwrap();
+        for child in &self.dir_childs {
+            child.collect_relocated_directories(res);
+        }
+    }
 
-        assert!(old_pos + file_entry_size == new_pos);
+    pub fn get_lba(&self, hierarchy: Hierarchy) -> u32 {
+        match hierarchy {
+            Hierarchy::Primary => self.lba,
+            Hierarchy::Joliet => self.joliet_lba,
+        }
+    }
 
-        Ok(())
+    /// Identifier of the directory in the given hierarchy
+    pub fn get_identifier(&self, hierarchy: Hierarchy) -> Vec<u8> {
+        match hierarchy {
+            Hierarchy::Primary => self.identifier.clone(),
+            Hierarchy::Joliet => self.joliet_identifier.clone(),
+        }
     }
 
-    pub fn get_path_table_size(&self) -> u32 {
+    pub fn get_path_table_size(&self, hierarchy: Hierarchy) -> u32 {
         let mut res = 0u32;
 
-        let file_name = self.path.file_name().unwrap().to_str().unwrap();
-
         let directory_type = if self.path_table_index == 1 { 5 } else { 6 };
 
-        res += utils::get_entry_size(0x8, file_name, directory_type, 0);
+        // The directories it holds are still part of the Joliet hierarchy
+        if hierarchy == Hierarchy::Primary || !self.relocation_directory {
+            res += utils::get_entry_size(
+                0x8,
+                &self.get_identifier(hierarchy),
+                directory_type,
+                0,
+                0,
+            );
+        }
 
         for entry in &self.dir_childs {
-            res += entry.get_path_table_size();
+            res += entry.get_path_table_size(hierarchy);
         }
 
         res
     }
 
-    pub fn get_extent_size_in_lb(&self) -> u32 {
-        let mut res = 1u32;
+    /// Size of the path table in logical blocks
+    pub fn get_path_table_size_in_lb(&self, hierarchy: Hierarchy) -> u32 {
+        self.get_path_table_size(hierarchy)
+            .div_ceil(LOGIC_SIZE_U32)
+            .max(1)
+    }
+
+    pub fn get_extent_size_in_lb(&self, hierarchy: Hierarchy) -> u32 {
         let mut size = 0u32;
 
-        size += self.get_entry_size(Some(3)); // '.'
-        size += self.get_entry_size(Some(2)); // '..'
+        size += self.get_entry_size(Some(3), hierarchy); // '.'
+        size += self.get_record_size(2, hierarchy, self.get_parent_link()); // '..'
 
-        for entry in &self.dir_childs {
-            let entry_size = entry.get_entry_size(Some(0));
+        let entry_sizes =
+            self.get_records(hierarchy).into_iter().flat_map(|record| {
+                match record {
+                    Record::Directory(entry) => {
+                        vec![entry.get_entry_size(Some(0), hierarchy)]
+                    }
+                    // One record per extent
+                    Record::File(entry) => vec![
+                        entry.get_entry_size(hierarchy);
+                        entry.get_extents().len()
+                    ],
+                }
+            });
+
+        // Records can't cross a logical block boundary, skip to the next one
+        // like `write_entry` does
+        for entry_size in entry_sizes {
             let expected_aligned_size =
                 utils::align_up_u32(size, LOGIC_SIZE_U32);
             let available_size_in_lb = expected_aligned_size - size;
 
-            if entry_size > available_size_in_lb && available_size_in_lb != 0 {
-                size = 0;
-                res += 1;
+            if entry_size > available_size_in_lb {
+                size = expected_aligned_size;
             }
 
             size += entry_size;
         }
 
-        for entry in &self.files_childs {
-            let entry_size = entry.get_entry_size();
-            let expected_aligned_size =
-                utils::align_up_u32(size, LOGIC_SIZE_U32);
-            let available_size_in_lb = expected_aligned_size - size;
-
-            if entry_size > available_size_in_lb && available_size_in_lb != 0 {
-                size = 0;
-                res += 1;
-            }
+        size.div_ceil(LOGIC_SIZE_U32)
+    }
 
-            size += entry_size;
-        }
+    /// Records of the extent in the given hierarchy, other than '.' and '..',
+    /// sorted by identifier (ECMA-119 9.3)
+    fn get_records(&self, hierarchy: Hierarchy) -> Vec<Record<'_>> {
+        let mut res: Vec<Record<'_>> = self
+            .dir_childs
+            .iter()
+            .filter(|entry| entry.is_recorded(hierarchy))
+            .map(Record::Directory)
+            .chain(self.files_childs.iter().map(Record::File))
+            .collect();
 
+        res.sort_by(|a, b| {
+            utils::compare_identifiers(
+                a.get_identifier(hierarchy),
+                b.get_identifier(hierarchy),
+                hierarchy,
+            )
+        });
         res
     }
 
-    pub fn get_entry_size(&self, directory_type: Option<u32>) -> u32 {
-        let file_name = self.path.file_name().unwrap().to_str().unwrap();
+    pub fn get_entry_size(
+        &self,
+        directory_type: Option<u32>,
+        hierarchy: Hierarchy,
+    ) -> u32 {
+        self.get_record_size(directory_type.unwrap_or(0), hierarchy, None)
+    }
 
-        utils::get_entry_size(0x21, file_name, directory_type.unwrap_or(0), 1)
+    fn get_record_size(
+        &self,
+        directory_type: u32,
+        hierarchy: Hierarchy,
+        parent_link: Option<u32>,
+    ) -> u32 {
+        let (system_use, continuation) = self
+            .get_record_system_use(directory_type, hierarchy, parent_link)
+            .unwrap();
+        utils::get_entry_size(
+            0x21,
+            &self.get_identifier(hierarchy),
+            directory_type,
+            1,
+            rock_ridge::get_system_use_size(&system_use, &continuation),
+        )
     }
 
     pub fn get_file_name(&self) -> String {
-        self.path.file_name().unwrap().to_str().unwrap().to_string()
+        self.name.clone()
     }
 
     fn write_path_table_entry<T, Order: ByteOrder>(
         directory_entry: &DirectoryEntry,
         output_writter: &mut T,
         directory_type: u32,
+        parent_index: u16,
+        hierarchy: Hierarchy,
     ) -> std::io::Result<()>
     where
         T: Write,
     {
-        let file_name =
-            directory_entry.path.file_name().unwrap().to_str().unwrap();
-
-        let file_name_fixed = utils::convert_name(file_name);
+        let file_name_fixed = directory_entry.get_identifier(hierarchy);
 
         let file_identifier = match directory_type {
             1 => &[0u8],
@@ -289,10 +470,9 @@ impl DirectoryEntry {
 
         output_writter.write_u8(file_identifier_len.try_into().unwrap())?;
         output_writter.write_u8(0x0u8)?;
-        output_writter.write_u32::<Order>(directory_entry.lba)?;
-        output_writter.write_u16::<Order>(
-            directory_entry.parent_index.try_into().unwrap(),
-        )?;
+        output_writter
+            .write_u32::<Order>(directory_entry.get_lba(hierarchy))?;
+        output_writter.write_u16::<Order>(parent_index)?;
         output_writter.write_all(file_identifier)?;
 
         // padding if odd
@@ -303,32 +483,50 @@ impl DirectoryEntry {
         Ok(())
     }
 
-    fn write_path_table_childs<T, Order: ByteOrder>(
-        &mut self,
-        output_writter: &mut T,
-    ) -> std::io::Result<()>
-    where
-        T: Write,
-    {
-        for entry in &mut self.dir_childs {
-            DirectoryEntry::write_path_table_entry::<T, Order>(
-                entry,
-                output_writter,
-                0,
-            )?;
-        }
+    /// Subdirectories in the given hierarchy, sorted by identifier,
+    /// `relocated` being the relocated directories by relocation identifier
+    pub fn get_subdirectories<'a>(
+        &'a self,
+        hierarchy: Hierarchy,
+        relocated: &HashMap<u32, &'a DirectoryEntry>,
+    ) -> Vec<&'a DirectoryEntry> {
+        self.get_records(hierarchy)
+            .into_iter()
+            .filter_map(|record| record.get_directory(hierarchy, relocated))
+            .collect()
+    }
 
-        for entry in &mut self.dir_childs {
-            entry.write_path_table_childs::<T, Order>(output_writter)?;
+    /// Directories in the order of the path table (ECMA-119 9.4): level by
+    /// level, then by parent and by identifier, along with the number of
+    /// their parent
+    fn get_path_table_directories<'a>(
+        &'a self,
+        hierarchy: Hierarchy,
+        relocated: &HashMap<u32, &'a DirectoryEntry>,
+    ) -> Vec<(&'a DirectoryEntry, u16)> {
+        let mut res = vec![(self, 1)];
+
+        let mut index = 0;
+        while let Some(&(directory, _)) = res.get(index) {
+            index += 1;
+            let parent_index = u16::try_from(index).unwrap();
+            res.extend(
+                directory
+                    .get_subdirectories(hierarchy, relocated)
+                    .into_iter()
+                    .map(|entry| (entry, parent_index)),
+            );
         }
 
-        Ok(())
+        res
     }
 
     pub fn write_path_table<T, Order: ByteOrder>(
-        &mut self,
+        &self,
         output_writter: &mut T,
         path_table_pos: u32,
+        hierarchy: Hierarchy,
+        relocated: &HashMap<u32, &DirectoryEntry>,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
@@ -336,37 +534,35 @@ impl DirectoryEntry {
         let old_pos = output_writter.stream_position()?;
 
         // Seek to the correct LBA
-        output_writter.seek(SeekFrom::Start(u64::from(
-            path_table_pos * LOGIC_SIZE_U32,
-        )))?;
+        output_writter.seek(SeekFrom::Start(
+            u64::from(path_table_pos) * u64::from(LOGIC_SIZE_U32),
+        ))?;
 
         let old_pos_current_context = output_writter.stream_position()?;
 
-        // Write root
-        DirectoryEntry::write_path_table_entry::<T, Order>(
-            self,
-            output_writter,
-            1,
-        )?;
-
-        self.write_path_table_childs::<T, Order>(output_writter)?;
+        // The root comes first
+        for (index, (directory, parent_index)) in self
+            .get_path_table_directories(hierarchy, relocated)
+            .into_iter()
+            .enumerate()
+        {
+            DirectoryEntry::write_path_table_entry::<T, Order>(
+                directory,
+                output_writter,
+                if index == 0 { 1 } else { 0 },
+                parent_index,
+                hierarchy,
+            )?;
+        }
 
         // Pad to LBA size
-        let current_pos: usize =
-            output_writter.stream_position()?.try_into().unwrap();
-        let expected_aligned_pos: usize = (i64::try_from(current_pos).unwrap()
-            & -LOGIC_SIZE_I64)
+        let current_pos = output_writter.stream_position()?;
+        let diff_size: usize = (current_pos % u64::from(LOGIC_SIZE_U32))
             .try_into()
             .unwrap();
 
-        let diff_size = current_pos - expected_aligned_pos;
-
-        let written_size =
-            current_pos - usize::try_from(old_pos_current_context).unwrap();
-        assert!(
-            written_size
-                == usize::try_from(self.get_path_table_size()).unwrap()
-        );
+        let written_size = current_pos - old_pos_current_context;
+        assert!(written_size == u64::from(self.get_path_table_size(hierarchy)));
 
         if diff_size != 0 {
             let padding: Vec<u8> = vec![0; LOGIC_SIZE - diff_size];
@@ -380,9 +576,11 @@ impl DirectoryEntry {
     }
 
     pub fn write_extent<T>(
-        &mut self,
+        &self,
         output_writter: &mut T,
         parent_option: Option<&DirectoryEntry>,
+        hierarchy: Hierarchy,
+        relocated: &HashMap<u32, &DirectoryEntry>,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
@@ -390,61 +588,74 @@ impl DirectoryEntry {
         let old_pos = output_writter.stream_position()?;
 
         // Seek to the correct LBA
-        output_writter
-            .seek(SeekFrom::Start(u64::from(self.lba * LOGIC_SIZE_U32)))?;
+        output_writter.seek(SeekFrom::Start(
+            u64::from(self.get_lba(hierarchy)) * u64::from(LOGIC_SIZE_U32),
+        ))?;
+
+        let mut continuation_areas =
+            ContinuationAreas::new(self.continuation_lba);
 
         let directory_type_current =
             if parent_option.is_none() { 3 } else { 1 };
 
-        self.write_as_current(output_writter, directory_type_current)?;
+        self.write_as_current(
+            output_writter,
+            directory_type_current,
+            hierarchy,
+            &mut continuation_areas,
+        )?;
 
-        let mut empty_parent_path = PathBuf::new();
-        empty_parent_path.set_file_name("dummy");
-        let empty_parent = DirectoryEntry {
-            path_table_index: 0,
-            parent_index: 0,
-            path: empty_parent_path,
-            dir_childs: Vec::new(),
-            files_childs: Vec::new(),
-            lba: self.lba,
-            continuation_area: None,
-        };
+        // The parent of the root is itself
+        let parent = parent_option.unwrap_or(self);
 
-        let parent = match parent_option {
-            Some(res) => res,
-            None => &empty_parent,
+        // Only Rock Ridge knows about relocations
+        let parent_link = match hierarchy {
+            Hierarchy::Primary => self.get_parent_link(),
+            Hierarchy::Joliet => None,
         };
+        parent.write_as_parent(
+            output_writter,
+            hierarchy,
+            &mut continuation_areas,
+            parent_link,
+        )?;
 
-        parent.write_as_parent(output_writter)?;
-
-        // FIXME: dirty
-        let self_clone = self.clone();
-
-        for child_directory in &mut self.dir_childs {
-            child_directory.write_one(output_writter)?;
-            child_directory.write_extent(output_writter, Some(&self_clone))?;
-        }
-
-        for child_file in &mut self.files_childs {
-            child_file.write_entry(output_writter)?;
+        for record in self.get_records(hierarchy) {
+            if let Some(child_directory) =
+                record.get_directory(hierarchy, relocated)
+            {
+                child_directory.write_one(
+                    output_writter,
+                    hierarchy,
+                    &mut continuation_areas,
+                )?;
+                child_directory.write_extent(
+                    output_writter,
+                    Some(self),
+                    hierarchy,
+                    relocated,
+                )?;
+            } else if let Record::File(child_file) = record {
+                child_file.write_entry(
+                    output_writter,
+                    hierarchy,
+                    &mut continuation_areas,
+                )?;
+            }
         }
 
         // Pad to LBA size
-        let current_pos: usize =
-            output_writter.stream_position()?.try_into().unwrap();
-        let expected_aligned_pos: usize = (i64::try_from(current_pos).unwrap()
-            & -LOGIC_SIZE_I64)
+        let current_pos = output_writter.stream_position()?;
+        let diff_size: usize = (current_pos % u64::from(LOGIC_SIZE_U32))
             .try_into()
             .unwrap();
 
-        let diff_size = current_pos - expected_aligned_pos;
-
         if diff_size != 0 {
             let padding: Vec<u8> = vec![0; LOGIC_SIZE - diff_size];
             output_writter.write_all(&padding)?;
         }
 
-        self.write_continuation_area(output_writter)?;
+        self.write_continuation_areas(output_writter, &continuation_areas)?;
 
         // Restore old position
         output_writter.seek(SeekFrom::Start(old_pos))?;
@@ -452,19 +663,26 @@ impl DirectoryEntry {
         Ok(())
     }
 
+    /// Write the content of the files, computing their digests if
+    /// `compute_digests` is set
     pub fn write_files<T>(
         &mut self,
         output_writter: &mut T,
-    ) -> std::io::Result<()>
+        compute_digests: bool,
+    ) -> Result<()>
     where
         T: Write + Seek,
     {
         for child_directory in &mut self.dir_childs {
-            child_directory.write_files(output_writter)?;
+            child_directory.write_files(output_writter, compute_digests)?;
         }
 
         for child_file in &mut self.files_childs {
-            child_file.write_content(output_writter)?;
+            if !child_file.has_content() || child_file.duplicate {
+                continue;
+            }
+
+            child_file.write_content(output_writter, compute_digests)?;
         }
         Ok(())
     }
@@ -473,58 +691,139 @@ impl DirectoryEntry {
         &self,
         output_writter: &mut T,
         directory_type: u32,
+        hierarchy: Hierarchy,
+        continuation_areas: &mut ContinuationAreas,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
-        DirectoryEntry::write_entry(self, output_writter, directory_type)
+        DirectoryEntry::write_entry(
+            self,
+            output_writter,
+            directory_type,
+            hierarchy,
+            continuation_areas,
+            None,
+        )
     }
 
     pub fn write_as_parent<T>(
         &self,
         output_writter: &mut T,
+        hierarchy: Hierarchy,
+        continuation_areas: &mut ContinuationAreas,
+        parent_link: Option<u32>,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
-        DirectoryEntry::write_entry(self, output_writter, 2)
+        DirectoryEntry::write_entry(
+            self,
+            output_writter,
+            2,
+            hierarchy,
+            continuation_areas,
+            parent_link,
+        )
     }
 
-    fn write_one<T>(&self, output_writter: &mut T) -> std::io::Result<()>
+    fn write_one<T>(
+        &self,
+        output_writter: &mut T,
+        hierarchy: Hierarchy,
+        continuation_areas: &mut ContinuationAreas,
+    ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
-        DirectoryEntry::write_entry(self, output_writter, 0)
+        DirectoryEntry::write_entry(
+            self,
+            output_writter,
+            0,
+            hierarchy,
+            continuation_areas,
+            None,
+        )
     }
 
-    fn write_continuation_area<T>(
+    /// Continuation areas of the records of the extent, in the order they are
+    /// written
+    fn get_continuation_areas(&self) -> std::io::Result<ContinuationAreas> {
+        let mut res = ContinuationAreas::new(self.continuation_lba);
+
+        // The continuation areas are only needed for the primary hierarchy,
+        // '..' records only carry 'PX', 'TF' and 'PL' entries, which always
+        // fit
+        let directory_type_current =
+            if self.path_table_index == 1 { 3 } else { 1 };
+        let (_, continuation) = self.get_record_system_use(
+            directory_type_current,
+            Hierarchy::Primary,
+            None,
+        )?;
+        if !continuation.is_empty() {
+            res.push(&continuation)?;
+        }
+
+        for record in self.get_records(Hierarchy::Primary) {
+            match record {
+                Record::Directory(entry) => {
+                    let (_, continuation) = entry.get_record_system_use(
+                        0,
+                        Hierarchy::Primary,
+                        None,
+                    )?;
+                    if !continuation.is_empty() {
+                        res.push(&continuation)?;
+                    }
+                }
+                Record::File(entry) => {
+                    let (_, continuation) =
+                        entry.get_record_system_use(Hierarchy::Primary)?;
+                    if !continuation.is_empty() {
+                        // One record per extent
+                        for _ in entry.get_extents() {
+                            res.push(&continuation)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Size of the continuation areas of the records in logical blocks
+    pub fn get_continuation_size_in_lb(&self) -> u32 {
+        self.get_continuation_areas().unwrap().size_in_lb()
+    }
+
+    fn write_continuation_areas<T>(
         &self,
         output_writter: &mut T,
+        continuation_areas: &ContinuationAreas,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
-        if let Some(data) = &self.continuation_area {
+        let data = continuation_areas.data();
+        if !data.is_empty() {
             let old_pos = output_writter.stream_position()?;
 
             // Seek to the correct LBA
-            output_writter.seek(SeekFrom::Start(u64::from(
-                (self.lba + self.get_extent_size_in_lb()) * LOGIC_SIZE_U32,
-            )))?;
+            output_writter.seek(SeekFrom::Start(
+                u64::from(self.continuation_lba) * u64::from(LOGIC_SIZE_U32),
+            ))?;
 
-            let mut tmp_cursor = Cursor::new(data.clone());
+            let mut tmp_cursor = Cursor::new(data);
 
             std::io::copy(&mut tmp_cursor, output_writter)?;
 
             // Pad to LBA size
-            let current_pos: usize =
-                output_writter.stream_position()?.try_into().unwrap();
-            let expected_aligned_pos: usize =
-                (i64::try_from(current_pos).unwrap() & -LOGIC_SIZE_I64)
-                    .try_into()
-                    .unwrap();
-
-            let diff_size = current_pos - expected_aligned_pos;
+            let current_pos = output_writter.stream_position()?;
+            let diff_size: usize = (current_pos % u64::from(LOGIC_SIZE_U32))
+                .try_into()
+                .unwrap();
 
             if diff_size != 0 {
                 let padding: Vec<u8> = vec![0; LOGIC_SIZE - diff_size];
@@ -545,9 +844,7 @@ impl DirectoryEntry {
         let mut res = None;
 
         for child in &mut self.dir_childs {
-            let file_name = child.path.file_name().unwrap().to_str().unwrap();
-
-            if file_name == dir_name {
+            if child.name == dir_name {
                 res = Some(child);
                 break;
             }
@@ -591,6 +888,12 @@ impl DirectoryEntry {
         res
     }
 
+    pub fn has_file(&self, file_name: &str) -> bool {
+        self.files_childs
+            .iter()
+            .any(|x| x.get_file_name() == file_name)
+    }
+
     pub fn add_file(&mut self, file: FileEntry) -> &FileEntry {
         self.files_childs.push(file);
         self.files_childs.last().unwrap()
@@ -636,79 +939,470 @@ impl DirectoryEntry {
         }
     }
 
-    pub fn set_path(&mut self, path: &[PathBuf]) -> std::io::Result<()> {
+    /// Merge the content of `other` into this directory
+    pub fn merge(&mut self, mut other: DirectoryEntry) {
+        if self.host_path.is_none() {
+            self.host_path = other.host_path.take();
+        }
+
+        self.files_childs.append(&mut other.files_childs);
+        self.merge_child_directories(other);
+    }
+
+    /// Drop the files imported from a previous session that were replaced by
+    /// an entry of the same name
+    pub fn drop_replaced_imports(&mut self) {
+        let names: Vec<String> = self
+            .files_childs
+            .iter()
+            .filter(|x| !x.is_imported())
+            .map(|x| x.get_file_name())
+            .chain(self.dir_childs.iter().map(|x| x.name.clone()))
+            .collect();
+        self.files_childs.retain(|x| {
+            !x.is_imported() || !names.contains(&x.get_file_name())
+        });
+
+        for child in &mut self.dir_childs {
+            child.drop_replaced_imports();
+        }
+    }
+
+    /// Mirror the content of the host paths, the files are added as they
+    /// are and the directories are walked, keeping the entries selected by
+    /// `filter`
+    pub fn set_path(
+        &mut self,
+        path: &[PathBuf],
+        filter: &HostFilter,
+    ) -> Result<()> {
+        self.mirror_path(path, filter, IgnoreRules::default(), "")
+    }
+
+    /// Mirror `path`, whose location relative to the mirrored directory is
+    /// `relative_path`, with the patterns of the ignore files of its parents
+    fn mirror_path(
+        &mut self,
+        path: &[PathBuf],
+        filter: &HostFilter,
+        mut rules: IgnoreRules,
+        relative_path: &str,
+    ) -> Result<()> {
         let mut dir_childs: Vec<DirectoryEntry> = Vec::new();
         let mut files_childs: Vec<FileEntry> = Vec::new();
 
         for path in path.iter().filter(|path| path.is_file()) {
-            let metadata = path.metadata().unwrap();
-            files_childs.push(FileEntry {
-                file_type: FileType::Regular {
-                    path: path.to_path_buf(),
-                },
-                size: metadata.len().try_into().unwrap(),
-                lba: 0,
-                aligned_size: utils::align_up_u32(
-                    metadata.len().try_into().unwrap(),
-                    LOGIC_SIZE_U32,
-                )
-                .try_into()
-                .unwrap(),
-            })
+            let name = utils::get_host_file_name(path)?;
+            let entry_path = format!("{relative_path}{name}");
+            if filter::is_filtered_out(filter, &rules, &entry_path, false) {
+                continue;
+            }
+
+            let metadata = path
+                .metadata()
+                .map_err(|err| IsoError::host_io(path, err))?;
+            let mut file = FileEntry::new_regular(
+                name,
+                path.to_path_buf(),
+                metadata.len().try_into().unwrap(),
+            );
+            file.posix = PosixAttributes::from_metadata(&metadata);
+            file.hidden = is_dot_file_hidden(filter, &entry_path);
+            files_childs.push(file);
         }
 
-        let mut ordered_dir: Vec<DirEntry> = path
-            .iter()
-            .filter(|path| path.is_dir())
-            .flat_map(|path| {
-                let res: Vec<DirEntry> =
-                    fs::read_dir(path).unwrap().map(|r| r.unwrap()).collect();
-                res
-            })
-            .collect();
+        let mut ordered_dir: Vec<DirEntry> = Vec::new();
+        for path in path.iter().filter(|path| path.is_dir()) {
+            if let Some(ignore_file) = &filter.ignore_file {
+                let ignore_path = path.join(ignore_file);
+                if ignore_path.is_file() {
+                    let content = fs::read_to_string(&ignore_path)
+                        .map_err(|err| IsoError::host_io(&ignore_path, err))?;
+                    rules.add_ignore_file(&content, relative_path);
+                }
+            }
+
+            for entry in fs::read_dir(path)
+                .map_err(|err| IsoError::host_io(path, err))?
+            {
+                ordered_dir
+                    .push(entry.map_err(|err| IsoError::host_io(path, err))?);
+            }
+        }
 
         ordered_dir.sort_by_key(|dir| dir.path());
 
         for entry in ordered_dir {
-            let entry_meta: Metadata = entry.metadata()?;
+            let entry_meta: Metadata = entry
+                .metadata()
+                .map_err(|err| IsoError::host_io(entry.path(), err))?;
+            let name = utils::get_host_file_name(&entry.path())?;
+            let entry_path = format!("{relative_path}{name}");
+            if filter::is_filtered_out(
+                filter,
+                &rules,
+                &entry_path,
+                entry_meta.is_dir(),
+            ) {
+                continue;
+            }
+            let hidden = is_dot_file_hidden(filter, &entry_path);
+
             if entry_meta.is_dir() {
                 let path_list: Vec<PathBuf> = vec![entry.path()];
                 let mut new_dir = DirectoryEntry::new()?;
-                new_dir.set_path(&path_list)?;
+                new_dir.mirror_path(
+                    &path_list,
+                    filter,
+                    rules.clone(),
+                    &format!("{entry_path}/"),
+                )?;
+                new_dir.hidden = hidden;
+
+                // Only keep the directories leading to included files
+                if !filter.include.is_empty()
+                    && new_dir.dir_childs.is_empty()
+                    && new_dir.files_childs.is_empty()
+                {
+                    continue;
+                }
+
                 DirectoryEntry::add_and_merge_childs_directories(
                     &mut dir_childs,
                     new_dir,
                 );
             } else if entry_meta.is_file() {
-                files_childs.push(FileEntry {
-                    file_type: FileType::Regular { path: entry.path() },
-                    size: entry_meta.len().try_into().unwrap(),
-                    lba: 0,
-                    aligned_size: utils::align_up_u32(
-                        entry_meta.len().try_into().unwrap(),
-                        LOGIC_SIZE_U32,
-                    )
-                    .try_into()
-                    .unwrap(),
-                })
+                let mut file = FileEntry::new_regular(
+                    name,
+                    entry.path(),
+                    entry_meta.len().try_into().unwrap(),
+                );
+                file.posix = PosixAttributes::from_metadata(&entry_meta);
+                file.hidden = hidden;
+                files_childs.push(file);
+            } else if entry_meta.is_symlink() {
+                let target = fs::read_link(entry.path())
+                    .map_err(|err| IsoError::host_io(entry.path(), err))?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| IsoError::InvalidFileName(entry.path()))?
+                    .to_string();
+
+                let mut file =
+                    FileEntry::new(FileType::Symlink { name, target }, 0);
+                file.posix = PosixAttributes::from_metadata(&entry_meta);
+                file.hidden = hidden;
+                files_childs.push(file);
             }
         }
 
-        self.path = path[0].clone();
+        let metadata = path[0]
+            .metadata()
+            .map_err(|err| IsoError::host_io(&path[0], err))?;
+        self.posix = PosixAttributes::from_metadata(&metadata);
+        self.name = utils::get_host_file_name(&path[0])?;
+        self.host_path = Some(path[0].clone());
         self.dir_childs.append(&mut dir_childs);
         self.files_childs.append(&mut files_childs);
         Ok(())
     }
 
+    /// Mark the entries matching `hidden_files` and `associated_files`,
+    /// `path` being the one of the directory ending with '/' unless empty
+    pub fn mark_files(
+        &mut self,
+        path: &str,
+        hidden_files: &[String],
+        associated_files: &[String],
+    ) {
+        let matches = |patterns: &[String], path: &str| {
+            patterns
+                .iter()
+                .any(|pattern| utils::glob_match(pattern, path))
+        };
+
+        for file in &mut self.files_childs {
+            let file_path = format!("{path}{}", file.get_file_name());
+            file.hidden |= matches(hidden_files, &file_path);
+            file.associated |= matches(associated_files, &file_path);
+        }
+
+        for directory in &mut self.dir_childs {
+            let directory_path = format!("{path}{}", directory.name);
+            directory.hidden |= matches(hidden_files, &directory_path);
+            directory.mark_files(
+                &format!("{directory_path}/"),
+                hidden_files,
+                associated_files,
+            );
+        }
+    }
+
+    /// Give every entry of the tree an ISO 9660 identifier of the given
+    /// interchange level and a Joliet identifier, both unique inside of its
+    /// directory
+    pub fn assign_identifiers(&mut self, level: InterchangeLevel) {
+        self.assign_child_identifiers(level);
+
+        // Joliet records the relocated directories at their original place
+        let mut joliet_identifiers = HashMap::new();
+        self.collect_placeholder_identifiers(&mut joliet_identifiers);
+        self.apply_placeholder_identifiers(&joliet_identifiers);
+    }
+
+    fn assign_child_identifiers(&mut self, level: InterchangeLevel) {
+        let mut mapper = NameMapper::new(level);
+        let mut joliet_mapper = JolietNameMapper::new();
+
+        for child in &mut self.dir_childs {
+            child.identifier = mapper.map(&child.name, true);
+            if child.is_recorded(Hierarchy::Joliet) {
+                child.joliet_identifier = joliet_mapper.map(&child.name, true);
+            }
+            child.assign_child_identifiers(level);
+        }
+
+        for child in &mut self.files_childs {
+            let name = child.get_file_name();
+            let is_relocated =
+                matches!(child.file_type, FileType::Relocated { .. });
+            child.identifier = mapper.map(&name, false);
+            child.joliet_identifier = joliet_mapper.map(&name, is_relocated);
+        }
+    }
+
+    /// Joliet identifiers of the placeholders of the relocated directories,
+    /// by relocation identifier
+    fn collect_placeholder_identifiers(&self, res: &mut HashMap<u32, Vec<u8>>) {
+        for file in &self.files_childs {
+            if let FileType::Relocated { id, .. } = file.file_type {
+                res.insert(id, file.joliet_identifier.clone());
+            }
+        }
+
+        for child in &self.dir_childs {
+            child.collect_placeholder_identifiers(res);
+        }
+    }
+
+    fn apply_placeholder_identifiers(
+        &mut self,
+        joliet_identifiers: &HashMap<u32, Vec<u8>>,
+    ) {
+        if let Some(relocation) = &self.relocation {
+            self.joliet_identifier = joliet_identifiers[&relocation.id].clone();
+        }
+
+        for child in &mut self.dir_childs {
+            child.apply_placeholder_identifiers(joliet_identifiers);
+        }
+    }
+
+    /// Move the directories nested deeper than `MAX_DIRECTORY_LEVEL` to a
+    /// relocation directory of the root, leaving a Rock Ridge placeholder in
+    /// their original parent
+    pub fn relocate_deep_directories(&mut self, build_time: DateTime<Utc>) {
+        let mut moved = VecDeque::new();
+        let mut next_id = 0;
+        self.take_deep_directories(1, &mut moved, &mut next_id);
+
+        if moved.is_empty() {
+            return;
+        }
+
+        // An existing directory with the same name is shared, like mkisofs
+        // does
+        let name = RELOCATION_DIRECTORY_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain((1..).map(|suffix| format!("rr_moved_{suffix}")))
+            .find(|name| !self.has_file(name))
+            .unwrap();
+        if self.get_directory(&name).is_none() {
+            let mut relocation_directory = DirectoryEntry::new().unwrap();
+            relocation_directory.name = name.clone();
+            relocation_directory.timestamp = build_time;
+            relocation_directory.relocation_directory = true;
+            self.dir_childs.push(relocation_directory);
+        }
+
+        // Relocated directories are on the third level, their own
+        // subdirectories can still be too deep
+        while let Some(mut directory) = moved.pop_front() {
+            directory.take_deep_directories(3, &mut moved, &mut next_id);
+            self.get_directory(&name)
+                .unwrap()
+                .dir_childs
+                .push(directory);
+        }
+    }
+
+    fn take_deep_directories(
+        &mut self,
+        level: usize,
+        moved: &mut VecDeque<DirectoryEntry>,
+        next_id: &mut u32,
+    ) {
+        if level < MAX_DIRECTORY_LEVEL {
+            for child in &mut self.dir_childs {
+                child.take_deep_directories(level + 1, moved, next_id);
+            }
+            return;
+        }
+
+        for mut child in std::mem::take(&mut self.dir_childs) {
+            let mut placeholder = FileEntry::new(
+                FileType::Relocated {
+                    name: child.name.clone(),
+                    id: *next_id,
+                },
+                0,
+            );
+            placeholder.posix = child.posix;
+            placeholder.timestamp = child.timestamp;
+            placeholder.hidden = child.hidden;
+            self.files_childs.push(placeholder);
+
+            child.relocation = Some(Relocation {
+                id: *next_id,
+                parent_lba: 0,
+            });
+            moved.push_back(child);
+            *next_id += 1;
+        }
+    }
+
+    /// Give every entry of the tree a serial number, starting at `next_serial`
+    pub fn assign_serials(&mut self, next_serial: &mut u32) {
+        self.serial = *next_serial;
+        *next_serial += 1;
+
+        for child in &mut self.files_childs {
+            child.serial = *next_serial;
+            *next_serial += 1;
+        }
+
+        for child in &mut self.dir_childs {
+            child.assign_serials(next_serial);
+        }
+    }
+
+    /// Replace the host owners and permissions of the whole tree by the
+    /// default ones
+    pub fn normalize_attributes(&mut self) {
+        self.posix.normalize();
+
+        for child in &mut self.dir_childs {
+            child.normalize_attributes();
+        }
+
+        for child in &mut self.files_childs {
+            child.posix.normalize();
+        }
+    }
+
+    /// Make sure that every name of the tree fits in its directory record
+    pub fn check_names(&self) -> Result<()> {
+        for child in &self.dir_childs {
+            if child.get_entry_size(Some(0), Hierarchy::Primary) > 0xFF {
+                return Err(IsoError::NameTooLong(child.name.clone()));
+            }
+
+            child.check_names()?;
+        }
+
+        for child in &self.files_childs {
+            if child.get_entry_size(Hierarchy::Primary) > 0xFF {
+                return Err(IsoError::NameTooLong(child.get_file_name()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make sure that files only span multiple extents when `level` allows it
+    pub fn check_file_sizes(&self, level: InterchangeLevel) -> Result<()> {
+        if level == InterchangeLevel::Level3 {
+            return Ok(());
+        }
+
+        for child in &self.dir_childs {
+            child.check_file_sizes(level)?;
+        }
+
+        for child in &self.files_childs {
+            if child.size > MAX_EXTENT_SIZE {
+                return Err(IsoError::FileTooLarge(child.get_file_name()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of directories of the tree, itself included
+    fn get_directory_count(&self) -> usize {
+        1 + self
+            .dir_childs
+            .iter()
+            .map(DirectoryEntry::get_directory_count)
+            .sum::<usize>()
+    }
+
+    /// Make sure that every directory can be numbered in the 16 bits parent
+    /// numbers of the path tables
+    pub fn check_directory_count(&self) -> Result<()> {
+        if self.get_directory_count() > usize::from(u16::MAX) {
+            return Err(IsoError::TooManyDirectories);
+        }
+
+        Ok(())
+    }
+
+    /// Set the recording time of the whole tree, `from_host_mtime` uses the
+    /// modification time of the mirrored files and directories instead
+    pub fn set_timestamp(
+        &mut self,
+        build_time: DateTime<Utc>,
+        from_host_mtime: bool,
+    ) -> Result<()> {
+        self.timestamp = match &self.host_path {
+            Some(host_path) if from_host_mtime => host_path
+                .metadata()
+                .and_then(|x| x.modified())
+                .map_err(|err| IsoError::host_io(host_path, err))?
+                .into(),
+            _ => build_time,
+        };
+
+        for child in &mut self.dir_childs {
+            child.set_timestamp(build_time, from_host_mtime)?;
+        }
+
+        for child in &mut self.files_childs {
+            child.set_timestamp(build_time, from_host_mtime)?;
+        }
+
+        Ok(())
+    }
+
     pub fn new() -> std::io::Result<DirectoryEntry> {
         Ok(DirectoryEntry {
             path_table_index: 0,
-            parent_index: 0,
-            path: PathBuf::new(),
+            name: String::new(),
+            identifier: Vec::new(),
+            joliet_identifier: Vec::new(),
+            host_path: None,
             dir_childs: Vec::new(),
             files_childs: Vec::new(),
             lba: 0,
-            continuation_area: None,
+            joliet_lba: 0,
+            continuation_lba: 0,
+            timestamp: DateTime::UNIX_EPOCH,
+            posix: PosixAttributes::new(rock_ridge::S_IFDIR),
+            serial: 0,
+            relocation: None,
+            relocation_directory: false,
+            hidden: false,
         })
     }
 }
@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, IsoError>;
+
+#[derive(Debug)]
+pub enum IsoError {
+    /// I/O error on the output image
+    Io(io::Error),
+    /// I/O error while accessing a host file or directory
+    HostIo { path: PathBuf, source: io::Error },
+    /// Host file name that isn't valid UTF-8
+    InvalidFileName(PathBuf),
+    /// Name that doesn't fit in a directory record
+    NameTooLong(String),
+    /// Path given to the builder that can't be used in the image
+    InvalidPath(String),
+    /// Entry already present at the given path
+    AlreadyExists(String),
+    /// No entry at the given path
+    NotFound(String),
+    /// Path going through a file instead of a directory
+    NotADirectory(String),
+    /// El Torito boot file not present in the image
+    BootFileMissing(String),
+    /// El Torito entries that don't fit in the boot catalog
+    TooManyBootEntries,
+    /// Embedded boot file that doesn't fit in the system area
+    EmbeddedBootTooLarge { path: PathBuf, size: u64 },
+    /// Volume descriptor identifier with invalid characters or too long
+    InvalidIdentifier { field: &'static str, value: String },
+    /// File bigger than a single extent, only allowed at interchange level 3
+    FileTooLarge(String),
+    /// Image that can't be addressed with 32 bits LBAs
+    ImageTooLarge,
+    /// More directories than the path tables can number
+    TooManyDirectories,
+    /// Invalid `SOURCE_DATE_EPOCH` environment variable
+    InvalidSourceDateEpoch(String),
+    /// Builder holding the files of an existing image, which can only be
+    /// appended to it
+    ImportedFiles,
+    /// Option changing the first session, used for an appended one
+    UnsupportedInSession(&'static str),
+    /// Checksums manifest that no longer fits in the space reserved for it
+    ManifestSizeChanged,
+    /// `Opt::checksums` used with an output that can't be read back
+    ChecksumsNeedReadableOutput,
+}
+
+impl IsoError {
+    pub(crate) fn host_io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        IsoError::HostIo {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for IsoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsoError::Io(err) => write!(f, "I/O error: {err}"),
+            IsoError::HostIo { path, source } => {
+                write!(f, "{}: {source}", path.display())
+            }
+            IsoError::InvalidFileName(path) => {
+                write!(f, "{}: file name isn't valid UTF-8", path.display())
+            }
+            IsoError::NameTooLong(name) => {
+                write!(f, "file name \"{name}\" is too long")
+            }
+            IsoError::InvalidPath(path) => {
+                write!(f, "\"{path}\" is not a valid ISO path")
+            }
+            IsoError::AlreadyExists(path) => {
+                write!(f, "\"{path}\" already exists")
+            }
+            IsoError::NotFound(path) => write!(f, "\"{path}\" not found"),
+            IsoError::NotADirectory(path) => {
+                write!(f, "a component of \"{path}\" is a file")
+            }
+            IsoError::BootFileMissing(path) => {
+                write!(f, "boot file \"{path}\" is missing from the image")
+            }
+            IsoError::TooManyBootEntries => {
+                write!(f, "too many El Torito entries for the boot catalog")
+            }
+            IsoError::EmbeddedBootTooLarge { path, size } => write!(
+                f,
+                "{}: generic boot file is bigger than 32768 bytes ({size} bytes)",
+                path.display()
+            ),
+            IsoError::InvalidIdentifier { field, value } => {
+                write!(f, "invalid {field} identifier \"{value}\"")
+            }
+            IsoError::FileTooLarge(name) => write!(
+                f,
+                "file \"{name}\" needs multiple extents, which requires interchange level 3"
+            ),
+            IsoError::ImageTooLarge => {
+                write!(f, "image is too large to be addressed")
+            }
+            IsoError::TooManyDirectories => {
+                write!(f, "too many directories for the path tables")
+            }
+            IsoError::InvalidSourceDateEpoch(value) => {
+                write!(f, "invalid SOURCE_DATE_EPOCH value \"{value}\"")
+            }
+            IsoError::ImportedFiles => write!(
+                f,
+                "the files of an existing image can only be appended to it"
+            ),
+            IsoError::UnsupportedInSession(option) => {
+                write!(f, "{option} can't be changed by an appended session")
+            }
+            IsoError::ManifestSizeChanged => write!(
+                f,
+                "the checksums manifest changed size while writing the files"
+            ),
+            IsoError::ChecksumsNeedReadableOutput => write!(
+                f,
+                "implanting the checksums needs an output that can be read back"
+            ),
+        }
+    }
+}
+
+impl Error for IsoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IsoError::Io(err) | IsoError::HostIo { source: err, .. } => {
+                Some(err)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for IsoError {
+    fn from(err: io::Error) -> Self {
+        IsoError::Io(err)
+    }
+}
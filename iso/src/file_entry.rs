@@ -1,49 +1,301 @@
-use crate::utils::{self, LOGIC_SIZE, LOGIC_SIZE_I64, LOGIC_SIZE_U32};
+use crate::checksum::DigestReader;
+use crate::content::ContentProvider;
+use crate::error::{IsoError, Result};
+use crate::rock_ridge::{self, ContinuationAreas, PosixAttributes};
+use crate::utils::{self, Hierarchy, LOGIC_SIZE, LOGIC_SIZE_U32};
+use crate::zisofs::{ZisofsFile, ZisofsReader};
 
 use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
 use chrono::prelude::*;
+use sha2::{Digest, Sha256};
 
+use std::fmt;
 use std::fs::File;
+use std::hash::{DefaultHasher, Hasher};
 use std::io;
-use std::io::Cursor;
 use std::io::SeekFrom;
 use std::io::prelude::*;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum FileType {
-    Regular { path: PathBuf },
-    Buffer { name: String, data: Vec<u8> },
+    Regular {
+        name: String,
+        path: PathBuf,
+    },
+    Buffer {
+        name: String,
+        data: Vec<u8>,
+    },
+    Provider {
+        name: String,
+        provider: ReadProvider,
+    },
+    /// Rock Ridge symbolic link, without any content
+    Symlink {
+        name: String,
+        target: String,
+    },
+    /// Rock Ridge placeholder of a directory moved to the relocation
+    /// directory, identified by `id`
+    Relocated {
+        name: String,
+        id: u32,
+    },
+    /// Content written by a previous session of the image, kept at its
+    /// `extents`
+    Imported {
+        name: String,
+        extents: Vec<(u32, u32)>,
+        /// Original size of a content stored compressed with zisofs
+        uncompressed_size: Option<u32>,
+    },
 }
 
+/// Content provider of a file, shared between the copies of the tree
+#[derive(Clone)]
+pub struct ReadProvider(pub Arc<dyn ContentProvider>);
+
+impl fmt::Debug for ReadProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReadProvider")
+    }
+}
+
+/// Largest extent a directory record can describe, in whole logical blocks so
+/// the extents of a file follow each other
+pub const MAX_EXTENT_SIZE: usize = 0xFFFF_F800;
+
 #[derive(Debug, Clone)]
 pub struct FileEntry {
     pub file_type: FileType,
+    /// ISO 9660 identifier without the version, unique in the parent
+    /// directory
+    pub identifier: Vec<u8>,
+    /// Joliet identifier without the version, unique in the parent directory
+    pub joliet_identifier: Vec<u8>,
     pub size: usize,
     pub lba: u32,
     pub aligned_size: usize,
+    pub timestamp: DateTime<Utc>,
+    pub posix: PosixAttributes,
+    /// Rock Ridge file serial number, unique in the image
+    pub serial: u32,
+    /// Shares the extent of another file with the same content, which is the
+    /// one writing it
+    pub duplicate: bool,
+    /// Stored compressed with zisofs, `size` is then the compressed size
+    pub zisofs: Option<ZisofsFile>,
+    /// SHA-256 digest of the content, computed while writing it when asked
+    pub sha256: Option<[u8; 32]>,
+    /// Recorded with the hidden flag
+    pub hidden: bool,
+    /// Recorded with the associated file flag
+    pub associated: bool,
 }
 
 impl FileEntry {
     pub fn get_file_name(&self) -> String {
         match &self.file_type {
-            FileType::Regular { path } => {
-                path.file_name().unwrap().to_str().unwrap().to_string()
-            }
-            FileType::Buffer { name, .. } => name.clone(),
+            FileType::Regular { name, .. }
+            | FileType::Buffer { name, .. }
+            | FileType::Provider { name, .. }
+            | FileType::Symlink { name, .. }
+            | FileType::Relocated { name, .. }
+            | FileType::Imported { name, .. } => name.clone(),
+        }
+    }
+
+    pub fn set_file_name(&mut self, new_name: String) {
+        match &mut self.file_type {
+            FileType::Regular { name, .. }
+            | FileType::Buffer { name, .. }
+            | FileType::Provider { name, .. }
+            | FileType::Symlink { name, .. }
+            | FileType::Relocated { name, .. }
+            | FileType::Imported { name, .. } => *name = new_name,
         }
     }
 
-    pub fn open_content_provider(&self) -> Box<dyn Read> {
+    /// Whether the file has content to write in the image
+    pub fn has_content(&self) -> bool {
+        !matches!(
+            self.file_type,
+            FileType::Symlink { .. }
+                | FileType::Relocated { .. }
+                | FileType::Imported { .. }
+        )
+    }
+
+    /// Whether the content comes from a previous session of the image
+    pub fn is_imported(&self) -> bool {
+        matches!(self.file_type, FileType::Imported { .. })
+    }
+
+    /// Attach the host path to an error happening while reading the content
+    pub fn error_context(&self, err: io::Error) -> IsoError {
         match &self.file_type {
-            FileType::Regular { path } => Box::new(File::open(path).unwrap()),
-            FileType::Buffer { data, .. } => {
-                Box::new(Cursor::new(data.clone()))
+            FileType::Regular { path, .. } => IsoError::host_io(path, err),
+            _ => IsoError::Io(err),
+        }
+    }
+
+    /// Open the content as stored in the image, limited to the size of the
+    /// file, `digest` is updated with the original content as it is read.
+    /// A compressed content comes from its blocks, the original isn't read
+    fn open_content<'a>(
+        &'a self,
+        digest: Option<&'a mut Sha256>,
+    ) -> Result<io::Take<Box<dyn Read + 'a>>> {
+        if let Some(zisofs) = &self.zisofs {
+            let content: Box<dyn Read + 'a> =
+                Box::new(ZisofsReader::new(zisofs));
+            return Ok(content.take(self.size.try_into().unwrap()));
+        }
+
+        let mut content: Box<dyn Read + 'a> = self
+            .open_content_provider()
+            .map_err(|err| self.error_context(err))?;
+        if let Some(digest) = digest {
+            content = Box::new(DigestReader {
+                inner: content,
+                digest,
+            });
+        }
+        Ok(content.take(self.size.try_into().unwrap()))
+    }
+
+    /// Store the content compressed with zisofs, unless it doesn't make it
+    /// smaller
+    pub fn compress(&mut self) -> Result<()> {
+        let content = self.open_content(None)?;
+        let zisofs = ZisofsFile::new(content, self.size)
+            .map_err(|err| self.error_context(err))?;
+
+        if let Some(zisofs) = zisofs {
+            self.size = zisofs.get_compressed_size();
+            self.aligned_size = self.size.next_multiple_of(LOGIC_SIZE);
+            self.zisofs = Some(zisofs);
+        }
+
+        Ok(())
+    }
+
+    /// Hash of the content, to find the files sharing the same content
+    pub fn get_content_hash(&self) -> Result<u64> {
+        let mut content = self.open_content(None)?;
+        let mut hasher = DefaultHasher::new();
+
+        let mut buff = vec![0; LOGIC_SIZE * 0x10];
+        loop {
+            let len = utils::read_full(&mut content, &mut buff)
+                .map_err(|err| self.error_context(err))?;
+            if len == 0 {
+                return Ok(hasher.finish());
+            }
+
+            hasher.write(&buff[..len]);
+        }
+    }
+
+    /// Compare the content with the one of `other`, a file of the same size
+    pub fn has_same_content(&self, other: &FileEntry) -> Result<bool> {
+        let mut content = self.open_content(None)?;
+        let mut other_content = other.open_content(None)?;
+
+        let mut buff = vec![0; LOGIC_SIZE * 0x10];
+        let mut other_buff = vec![0; LOGIC_SIZE * 0x10];
+        loop {
+            let len = utils::read_full(&mut content, &mut buff)
+                .map_err(|err| self.error_context(err))?;
+            let other_len =
+                utils::read_full(&mut other_content, &mut other_buff)
+                    .map_err(|err| other.error_context(err))?;
+
+            if buff[..len] != other_buff[..other_len] {
+                return Ok(false);
             }
+            if len == 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Open the original content, as given to the builder
+    pub fn open_content_provider(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.file_type {
+            FileType::Regular { path, .. } => Ok(Box::new(File::open(path)?)),
+            FileType::Buffer { data, .. } => Ok(Box::new(&data[..])),
+            FileType::Provider { provider, .. } => provider.0.open(),
+            FileType::Symlink { .. }
+            | FileType::Relocated { .. }
+            | FileType::Imported { .. } => Ok(Box::new(io::empty())),
+        }
+    }
+
+    /// Location and size of every extent of the file, files bigger than
+    /// `MAX_EXTENT_SIZE` are split in consecutive extents (ECMA-119 6.5.1)
+    pub fn get_extents(&self) -> Vec<(u32, u32)> {
+        if let FileType::Imported { extents, .. } = &self.file_type {
+            return extents.clone();
+        }
+        if self.size == 0 {
+            return vec![(self.lba, 0)];
+        }
+
+        let extent_size_in_lb =
+            u32::try_from(MAX_EXTENT_SIZE / LOGIC_SIZE).unwrap();
+        (0..self.size)
+            .step_by(MAX_EXTENT_SIZE)
+            .zip(0..)
+            .map(|(offset, index)| {
+                (
+                    self.lba + index * extent_size_in_lb,
+                    u32::try_from((self.size - offset).min(MAX_EXTENT_SIZE))
+                        .unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn write_entry<T>(
+        &self,
+        output_writter: &mut T,
+        hierarchy: Hierarchy,
+        continuation_areas: &mut ContinuationAreas,
+    ) -> std::io::Result<()>
+    where
+        T: Write + Seek,
+    {
+        let extents = self.get_extents();
+        let extent_count = extents.len();
+
+        // One record per extent, all but the last one flagged as such
+        for (index, (lba, size)) in extents.into_iter().enumerate() {
+            let is_final = index + 1 == extent_count;
+            self.write_extent_entry(
+                output_writter,
+                hierarchy,
+                lba,
+                size,
+                is_final,
+                continuation_areas,
+            )?;
         }
+
+        Ok(())
     }
 
-    pub fn write_entry<T>(&self, output_writter: &mut T) -> std::io::Result<()>
+    fn write_extent_entry<T>(
+        &self,
+        output_writter: &mut T,
+        hierarchy: Hierarchy,
+        lba: u32,
+        size: u32,
+        is_final: bool,
+        continuation_areas: &mut ContinuationAreas,
+    ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
@@ -52,7 +304,7 @@ impl FileEntry {
             current_pos.next_multiple_of(LOGIC_SIZE_U32.into());
 
         let diff_size = expected_aligned_pos - current_pos;
-        let file_entry_size = self.get_entry_size();
+        let file_entry_size = self.get_entry_size(hierarchy);
 
         if u64::from(file_entry_size) > diff_size && diff_size != 0 {
             let padding: Vec<u8> = vec![0; diff_size.try_into().unwrap()];
@@ -61,9 +313,11 @@ impl FileEntry {
 
         let old_pos = output_writter.stream_position()?;
 
-        let file_name = self.get_file_name();
-        let file_identifier = utils::convert_name(&file_name);
-        let file_identifier_len = file_identifier.len() + 2;
+        let (file_identifier, version): (&[u8], &[u8]) = match hierarchy {
+            Hierarchy::Primary => (&self.identifier, b";1"),
+            Hierarchy::Joliet => (&self.joliet_identifier, b"\x00;\x001"),
+        };
+        let file_identifier_len = file_identifier.len() + version.len();
 
         output_writter.write_u8(file_entry_size.try_into().unwrap())?;
 
@@ -72,28 +326,26 @@ impl FileEntry {
 
         // Location of extent (in LB)
         write_bothendian! {
-            output_writter.write_u32(self.lba)?;
+            output_writter.write_u32(lba)?;
         }
 
         // Extent size
         write_bothendian! {
-            output_writter.write_u32(self.size.try_into().unwrap())?;
-        }
-
-        let record_datetime: DateTime<Utc> = Utc::now();
-        output_writter
-            .write_u8((record_datetime.year() - 1900).try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.month().try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.day().try_into().unwrap())?;
-        output_writter.write_u8(record_datetime.hour().try_into().unwrap())?;
-        output_writter
-            .write_u8(record_datetime.minute().try_into().unwrap())?;
-        output_writter
-            .write_u8(record_datetime.second().try_into().unwrap())?;
-        output_writter.write_u8(0u8)?;
+            output_writter.write_u32(size)?;
+        }
 
-        // file flags
-        output_writter.write_u8(0x0u8)?;
+        utils::write_record_datetime(output_writter, &self.timestamp)?;
+
+        // file flags: hidden, associated file, and multi-extent on all but
+        // the last record
+        let mut flags = if is_final { 0x0 } else { 0x80 };
+        if self.hidden {
+            flags |= 0x1;
+        }
+        if self.associated {
+            flags |= 0x4;
+        }
+        output_writter.write_u8(flags)?;
 
         output_writter.write_u8(0x0u8)?;
         output_writter.write_u8(0x0u8)?;
@@ -103,64 +355,128 @@ impl FileEntry {
         }
 
         output_writter.write_u8(file_identifier_len.try_into().unwrap())?;
-        output_writter.write_all(&file_identifier[..])?;
-        output_writter.write_all(b";1")?;
+        output_writter.write_all(file_identifier)?;
+        output_writter.write_all(version)?;
 
         // padding if even
         if file_identifier_len.is_multiple_of(2) {
             output_writter.write_u8(0x0u8)?;
         }
 
-        // RRIP 'PX' entry (IEEE P1282 4.1.1)
-        output_writter.write_all(b"PX")?;
-        output_writter.write_u8(0x2c)?;
-        output_writter.write_u8(0x1)?;
-
-        // file mode
-        write_bothendian! {
-            output_writter.write_u32(0o100_644)?; // harcoded rw-r--r--
+        let (mut system_use, continuation) =
+            self.get_record_system_use(hierarchy)?;
+        if !continuation.is_empty() {
+            system_use.extend(continuation_areas.push(&continuation)?);
         }
-
-        // links
-        write_bothendian! {
-            output_writter.write_u32(0x1)?; // one link
+        // Records have an even length
+        if !system_use.len().is_multiple_of(2) {
+            system_use.push(0);
         }
+        output_writter.write_all(&system_use)?;
 
-        // user id
-        write_bothendian! {
-            output_writter.write_u32(0x0)?; // root
-        }
+        let new_pos = output_writter.stream_position()?;
 
-        // group id
-        write_bothendian! {
-            output_writter.write_u32(0x0)?; // root
+        assert!(old_pos + u64::from(file_entry_size) == new_pos);
+
+        Ok(())
+    }
+
+    pub fn set_timestamp(
+        &mut self,
+        build_time: DateTime<Utc>,
+        from_host_mtime: bool,
+    ) -> Result<()> {
+        self.timestamp = match &self.file_type {
+            FileType::Regular { path, .. } if from_host_mtime => path
+                .metadata()
+                .and_then(|x| x.modified())
+                .map_err(|err| IsoError::host_io(path, err))?
+                .into(),
+            // Recorded by the previous session
+            FileType::Imported { .. } => self.timestamp,
+            _ => build_time,
+        };
+
+        Ok(())
+    }
+
+    /// Rock Ridge entries of the record
+    fn get_system_use(&self, hierarchy: Hierarchy) -> std::io::Result<Vec<u8>> {
+        let mut res = Vec::new();
+
+        // Joliet hierarchy doesn't carry any Rock Ridge entries
+        if hierarchy == Hierarchy::Joliet {
+            return Ok(res);
         }
 
-        // "File Serial number"
-        write_bothendian! {
-            // dirty way to generate an inode but I guess it's fine
-            output_writter.write_u32(self.lba)?;
+        rock_ridge::write_px(&mut res, &self.posix, 1, self.serial)?;
+        rock_ridge::write_tf(&mut res, &self.timestamp)?;
+        rock_ridge::write_nm(&mut res, &self.get_file_name())?;
+
+        match &self.file_type {
+            FileType::Symlink { target, .. } => {
+                rock_ridge::write_sl(&mut res, target)?;
+            }
+            // The directory is at the location of the record
+            FileType::Relocated { .. } => {
+                rock_ridge::write_cl(&mut res, self.lba)?;
+            }
+            FileType::Imported {
+                uncompressed_size: Some(uncompressed_size),
+                ..
+            } => {
+                rock_ridge::write_zf(&mut res, *uncompressed_size)?;
+            }
+            _ => {}
         }
 
-        // RRIP 'NM' entry (IEEE P1282 4.1.4)
-        output_writter.write_all(b"NM")?;
-        output_writter.write_u8((0x5 + file_name.len()).try_into().unwrap())?;
-        output_writter.write_u8(0x1)?;
-        output_writter.write_u8(0x0)?; // No flags
-        output_writter.write_all(file_name.as_bytes())?;
+        if let Some(zisofs) = &self.zisofs {
+            rock_ridge::write_zf(&mut res, zisofs.uncompressed_size)?;
+        }
 
-        let new_pos = output_writter.stream_position()?;
+        Ok(res)
+    }
 
-        assert!(old_pos + u64::from(file_entry_size) == new_pos);
+    /// Size of the record without its system use
+    fn get_base_entry_size(&self, hierarchy: Hierarchy) -> u32 {
+        // don't miss to count the ";1"! Joliet records a relocated directory
+        // at the place of its placeholder, without any version
+        let (file_identifier, version_len) = match (hierarchy, &self.file_type)
+        {
+            (Hierarchy::Primary, _) => (&self.identifier, 2),
+            (Hierarchy::Joliet, FileType::Relocated { .. }) => {
+                (&self.joliet_identifier, 0)
+            }
+            (Hierarchy::Joliet, _) => (&self.joliet_identifier, 4),
+        };
 
-        Ok(())
+        utils::get_entry_size(0x21 + version_len, file_identifier, 0, 1, 0)
     }
 
-    pub fn get_entry_size(&self) -> u32 {
-        let file_name = self.get_file_name();
+    /// Rock Ridge entries kept in the record, without the 'CE' entry, and the
+    /// ones moved to its continuation area
+    pub fn get_record_system_use(
+        &self,
+        hierarchy: Hierarchy,
+    ) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+        let available = 0xFF
+            - usize::try_from(self.get_base_entry_size(hierarchy)).unwrap();
+
+        Ok(rock_ridge::split_system_use(
+            &self.get_system_use(hierarchy)?,
+            available,
+        ))
+    }
 
-        // don't miss to count the ";1"!
-        utils::get_entry_size(0x21 + 2, &file_name, 0, 1)
+    pub fn get_entry_size(&self, hierarchy: Hierarchy) -> u32 {
+        let (system_use, continuation) =
+            self.get_record_system_use(hierarchy).unwrap();
+        self.get_base_entry_size(hierarchy)
+            + u32::try_from(rock_ridge::get_system_use_size(
+                &system_use,
+                &continuation,
+            ))
+            .unwrap()
     }
 
     pub fn update(&mut self) {
@@ -178,31 +494,65 @@ impl FileEntry {
         }
     }
 
+    /// Write the content at the location of the file, computing its digest
+    /// if `compute_digest` is set
     pub fn write_content<T>(
         &mut self,
         output_writter: &mut T,
-    ) -> std::io::Result<()>
+        compute_digest: bool,
+    ) -> Result<()>
     where
         T: Write + Seek,
     {
         let old_pos = output_writter.stream_position()?;
 
         // Seek to the correct LBA
-        output_writter
-            .seek(SeekFrom::Start(u64::from(self.lba * LOGIC_SIZE_U32)))?;
+        output_writter.seek(SeekFrom::Start(
+            u64::from(self.lba) * u64::from(LOGIC_SIZE_U32),
+        ))?;
+
+        let mut digest = compute_digest.then(Sha256::new);
+        let mut file = self.open_content(digest.as_mut())?;
+
+        // Copy by hand to tell apart errors of the source and of the output
+        let mut buff = vec![0; LOGIC_SIZE * 0x10];
+        let mut written_size = 0;
+        loop {
+            let len = match file.read(&mut buff) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    continue;
+                }
+                Err(err) => return Err(self.error_context(err)),
+            };
+
+            output_writter.write_all(&buff[..len])?;
+            written_size += len;
+        }
 
-        let mut file: Box<dyn Read> = self.open_content_provider();
-        io::copy(&mut file, output_writter)?;
+        if written_size != self.size {
+            return Err(self.error_context(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "file is smaller than expected ({written_size} bytes instead of {})",
+                    self.size
+                ),
+            )));
+        }
+
+        drop(file);
+        self.sha256 = match &self.zisofs {
+            // Hashed while compressing it
+            Some(zisofs) => compute_digest.then_some(zisofs.sha256),
+            None => digest.map(|digest| digest.finalize().into()),
+        };
 
-        let current_pos: usize =
-            output_writter.stream_position()?.try_into().unwrap();
-        let expected_aligned_pos: usize = (i64::try_from(current_pos).unwrap()
-            & -LOGIC_SIZE_I64)
+        let current_pos = output_writter.stream_position()?;
+        let diff_size: usize = (current_pos % u64::from(LOGIC_SIZE_U32))
             .try_into()
             .unwrap();
 
-        let diff_size = current_pos - expected_aligned_pos;
-
         if diff_size != 0 {
             let padding: Vec<u8> = vec![0; LOGIC_SIZE - diff_size];
             output_writter.write_all(&padding)?;
@@ -213,15 +563,46 @@ impl FileEntry {
         Ok(())
     }
 
-    pub fn new_buffered(name: String) -> FileEntry {
+    pub fn new(file_type: FileType, size: usize) -> FileEntry {
+        let posix = match file_type {
+            FileType::Symlink { .. } => {
+                PosixAttributes::new(rock_ridge::S_IFLNK)
+            }
+            FileType::Relocated { .. } => {
+                PosixAttributes::new(rock_ridge::S_IFDIR)
+            }
+            _ => PosixAttributes::new(rock_ridge::S_IFREG),
+        };
+
         FileEntry {
-            file_type: FileType::Buffer {
+            file_type,
+            identifier: Vec::new(),
+            joliet_identifier: Vec::new(),
+            lba: 0,
+            size,
+            aligned_size: size.next_multiple_of(LOGIC_SIZE),
+            timestamp: DateTime::UNIX_EPOCH,
+            posix,
+            serial: 0,
+            duplicate: false,
+            zisofs: None,
+            sha256: None,
+            hidden: false,
+            associated: false,
+        }
+    }
+
+    pub fn new_regular(name: String, path: PathBuf, size: usize) -> FileEntry {
+        FileEntry::new(FileType::Regular { name, path }, size)
+    }
+
+    pub fn new_buffered(name: String) -> FileEntry {
+        FileEntry::new(
+            FileType::Buffer {
                 name,
                 data: Vec::new(),
             },
-            lba: 0,
-            size: 0,
-            aligned_size: 0,
-        }
+            0,
+        )
     }
 }
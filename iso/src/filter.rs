@@ -0,0 +1,102 @@
+use crate::option::{DotFiles, HostFilter};
+use crate::utils;
+
+/// Pattern of an ignore file, as a glob relative to the mirrored directory
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    glob: String,
+    /// Keeps the entries instead, `!` prefix
+    negated: bool,
+    /// Only matches directories, `/` suffix
+    directory_only: bool,
+}
+
+/// Patterns of the ignore files met while walking a host directory, the last
+/// matching one applies
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreRules {
+    /// Add the patterns of an ignore file found in `base`, relative to the
+    /// mirrored directory and ending with '/' unless empty. Supports the
+    /// `.gitignore` comments, negations, and directory only and anchored
+    /// patterns, but not the escapes.
+    pub fn add_ignore_file(&mut self, content: &str, base: &str) {
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (directory_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+
+            // Patterns with a '/' are relative to the ignore file directory,
+            // the other ones match at any depth
+            let glob = if line.contains('/') {
+                format!("{base}{}", line.trim_start_matches('/'))
+            } else {
+                format!("{base}**/{line}")
+            };
+
+            self.patterns.push(IgnorePattern {
+                glob,
+                negated,
+                directory_only,
+            });
+        }
+    }
+
+    fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                (is_dir || !pattern.directory_only)
+                    && utils::glob_match(&pattern.glob, path)
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Whether the host entry at `path`, relative to the mirrored directory, is
+/// left out of the image
+pub fn is_filtered_out(
+    filter: &HostFilter,
+    rules: &IgnoreRules,
+    path: &str,
+    is_dir: bool,
+) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    if filter.dot_files == DotFiles::Skip && name.starts_with('.') {
+        return true;
+    }
+    if filter.ignore_file.as_deref() == Some(name) {
+        return true;
+    }
+
+    // Directories are walked for included files
+    if !is_dir
+        && !filter.include.is_empty()
+        && !filter
+            .include
+            .iter()
+            .any(|pattern| utils::glob_match(pattern, path))
+    {
+        return true;
+    }
+
+    filter
+        .exclude
+        .iter()
+        .any(|pattern| utils::glob_match(pattern, path))
+        || rules.is_ignored(path, is_dir)
+}
@@ -2,134 +2,409 @@
 
 #[macro_use]
 mod utils;
+mod builder;
+mod checksum;
+mod content;
 mod directory_entry;
+mod error;
 mod file_entry;
+mod filter;
 pub mod option;
+pub mod reader;
+mod rock_ridge;
+mod session;
+mod verify;
 mod volume_descriptor;
+mod zisofs;
 
-use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use chrono::prelude::*;
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Cursor;
 use std::io::SeekFrom;
 use std::io::prelude::*;
 use std::path::PathBuf;
-use std::str::FromStr;
+
+pub use crate::builder::IsoBuilder;
+pub use crate::checksum::{MANIFEST_NAME, implant_checksums, verify_checksums};
+pub use crate::content::ContentProvider;
+pub use crate::error::{IsoError, Result};
+pub use crate::verify::{Diagnostic, Severity, verify};
 
 use crate::{
     directory_entry::DirectoryEntry,
     file_entry::{FileEntry, FileType},
-    utils::{LOGIC_SIZE, LOGIC_SIZE_U32, SECTOR_SIZE},
+    reader::IsoImage,
+    utils::{Hierarchy, LOGIC_SIZE, LOGIC_SIZE_U32, SECTOR_SIZE},
     volume_descriptor::VolumeDescriptor,
 };
 
-fn assign_directory_identifiers(
-    tree: &mut DirectoryEntry,
-    last_index: &mut u32,
-    last_lba: &mut u32,
+/// Number the directories in the order of the primary path table, the root
+/// being 1
+fn assign_path_table_indexes(tree: &mut DirectoryEntry) {
+    tree.path_table_index = 1;
+
+    let mut last_index = tree.path_table_index;
+    let mut level: Vec<&mut DirectoryEntry> = vec![tree];
+    while !level.is_empty() {
+        let mut next_level = Vec::new();
+
+        for directory in level {
+            let mut childs: Vec<&mut DirectoryEntry> =
+                directory.dir_childs.iter_mut().collect();
+            childs.sort_by(|a, b| {
+                utils::compare_identifiers(
+                    &a.identifier,
+                    &b.identifier,
+                    Hierarchy::Primary,
+                )
+            });
+
+            for entry in childs {
+                last_index += 1;
+                entry.path_table_index = last_index;
+                next_level.push(entry);
+            }
+        }
+
+        level = next_level;
+    }
+}
+
+fn assign_directory_lba(tree: &mut DirectoryEntry, last_lba: &mut u32) {
+    tree.lba = *last_lba;
+    *last_lba += tree.get_extent_size_in_lb(Hierarchy::Primary);
+
+    // Continuation areas of the SUSP entries, just after the directory
+    // records
+    tree.continuation_lba = *last_lba;
+    *last_lba += tree.get_continuation_size_in_lb();
+
+    // The relocation directory goes first, Rock Ridge readers resolving the
+    // placeholders in reading order need to see the relocated directories
+    // before the directories they were moved from
+    let (relocations, others): (Vec<_>, Vec<_>) =
+        tree.dir_childs.iter_mut().partition(|entry| {
+            entry.dir_childs.iter().any(|x| x.relocation.is_some())
+        });
+    for entry in relocations.into_iter().chain(others) {
+        assign_directory_lba(entry, last_lba);
+    }
+}
+
+/// Locations of the relocated directories and of their original parents, by
+/// relocation identifier
+fn collect_relocations(
+    tree: &DirectoryEntry,
+    directory_lbas: &mut HashMap<u32, u32>,
+    parent_lbas: &mut HashMap<u32, u32>,
 ) {
-    // Reserve CE space for SUSP
-    if tree.continuation_area.is_some() {
-        *last_lba += 1;
+    if let Some(relocation) = tree.relocation {
+        directory_lbas.insert(relocation.id, tree.lba);
     }
 
-    if *last_index == 0 {
-        tree.parent_index = *last_index;
-        tree.path_table_index = *last_index + 1;
+    for file in &tree.files_childs {
+        if let FileType::Relocated { id, .. } = file.file_type {
+            parent_lbas.insert(id, tree.lba);
+        }
+    }
 
-        *last_index = tree.path_table_index;
-    } else {
-        tree.lba = *last_lba;
+    for entry in &tree.dir_childs {
+        collect_relocations(entry, directory_lbas, parent_lbas);
+    }
+}
+
+fn apply_relocations(
+    tree: &mut DirectoryEntry,
+    directory_lbas: &HashMap<u32, u32>,
+    parent_lbas: &HashMap<u32, u32>,
+) {
+    if let Some(relocation) = &mut tree.relocation {
+        relocation.parent_lba = parent_lbas[&relocation.id];
+    }
+
+    for file in &mut tree.files_childs {
+        if let FileType::Relocated { id, .. } = file.file_type {
+            file.lba = directory_lbas[&id];
+        }
     }
-    *last_lba += tree.get_extent_size_in_lb();
 
     for entry in &mut tree.dir_childs {
-        entry.parent_index = tree.path_table_index;
-        entry.path_table_index = *last_index + 1;
+        apply_relocations(entry, directory_lbas, parent_lbas);
+    }
+}
 
-        *last_index = entry.path_table_index;
+/// Point the Rock Ridge placeholders of the relocated directories and the
+/// relocated directories to each other, once the directories are placed
+fn resolve_relocations(tree: &mut DirectoryEntry) {
+    let mut directory_lbas = HashMap::new();
+    let mut parent_lbas = HashMap::new();
+    collect_relocations(tree, &mut directory_lbas, &mut parent_lbas);
+    apply_relocations(tree, &directory_lbas, &parent_lbas);
+}
+
+fn assign_joliet_directory_lba(tree: &mut DirectoryEntry, last_lba: &mut u32) {
+    // Joliet has no relocation directory, unlike the directories it holds
+    if !tree.relocation_directory {
+        tree.joliet_lba = *last_lba;
+        *last_lba += tree.get_extent_size_in_lb(Hierarchy::Joliet);
     }
 
     for entry in &mut tree.dir_childs {
-        assign_directory_identifiers(entry, last_index, last_lba);
+        assign_joliet_directory_lba(entry, last_lba);
     }
 }
 
-fn reserve_file_space(
-    directory_entry: &mut DirectoryEntry,
-    current_lba: &mut u32,
+/// Files having a content, along with their path in the image, in tree order
+fn collect_content_files<'a>(
+    directory_entry: &'a mut DirectoryEntry,
+    path: &str,
+    res: &mut Vec<(String, &'a mut FileEntry)>,
 ) {
     for child_file in &mut directory_entry.files_childs {
-        let lba_count = (u32::try_from(child_file.size).unwrap()
-            + LOGIC_SIZE_U32)
-            / LOGIC_SIZE_U32;
-        child_file.lba = *current_lba;
-        *current_lba += lba_count;
+        // Symbolic links and relocated directories placeholders don't have
+        // any content
+        if !child_file.has_content() {
+            continue;
+        }
+
+        res.push((format!("{path}{}", child_file.get_file_name()), child_file));
     }
 
     for child_directory in &mut directory_entry.dir_childs {
-        reserve_file_space(child_directory, current_lba);
+        let path = format!("{path}{}/", child_directory.name);
+        collect_content_files(child_directory, &path, res);
     }
 }
 
-const DEFAULT_PRIMARY_NAME: &[u8] = b"ISOIMAGE                        ";
-fn generate_volume_descriptors(opt: &option::Opt) -> Vec<VolumeDescriptor> {
-    let mut res: Vec<VolumeDescriptor> = Vec::new();
+/// Paths in the image of the files written once placed: the boot catalog,
+/// the El Torito boot files and the checksums manifest
+fn get_patched_paths(opt: &option::Opt) -> Vec<String> {
+    let mut res = vec![String::from("boot.catalog")];
+    if opt.checksums {
+        res.push(String::from(checksum::MANIFEST_NAME));
+    }
+    res.extend(opt.eltorito_opt.eltorito_boot.clone());
+    res.extend(
+        opt.eltorito_opt
+            .entries
+            .iter()
+            .map(|entry| entry.boot_file.clone()),
+    );
 
-    res.push(VolumeDescriptor::Primary(
-        opt.primary_volume_name
-            .clone()
-            .map_or(DEFAULT_PRIMARY_NAME.into(), |x| x.into_bytes().into()),
-    ));
-    if opt.eltorito_opt.eltorito_boot.is_some() {
-        res.push(VolumeDescriptor::Boot);
+    res.into_iter()
+        .map(|x| x.trim_start_matches('/').to_owned())
+        .collect()
+}
+
+/// Compress with zisofs the files matching `opt.compressed_files`
+fn compress_files(tree: &mut DirectoryEntry, opt: &option::Opt) -> Result<()> {
+    if opt.compressed_files.is_empty() {
+        return Ok(());
     }
-    res.push(VolumeDescriptor::End);
 
-    res
+    // Firmwares read the El Torito files as they are, the manifest is only
+    // known at the end
+    let patched_paths = get_patched_paths(opt);
+
+    let mut files = Vec::new();
+    collect_content_files(tree, "", &mut files);
+    for (path, file) in files {
+        if patched_paths.contains(&path)
+            || !opt
+                .compressed_files
+                .iter()
+                .any(|pattern| utils::glob_match(pattern, &path))
+        {
+            continue;
+        }
+
+        file.compress()?;
+    }
+
+    Ok(())
 }
 
-fn create_boot_catalog(tree: &mut DirectoryEntry) {
-    let catalog_file = FileEntry::new_buffered(String::from("boot.catalog"));
-    tree.add_file(catalog_file);
+/// Index of an earlier file with the same content for every file, if any
+fn find_duplicates(
+    files: &[(String, Option<&option::SortRule>, &mut FileEntry)],
+    opt: &option::Opt,
+) -> Result<Vec<Option<usize>>> {
+    // Files patched once placed can't be shared
+    let unique_paths = get_patched_paths(opt);
+
+    // Only the files sharing their size with another one are read
+    let mut size_counts: HashMap<usize, usize> = HashMap::new();
+    for (_, _, file) in files {
+        *size_counts.entry(file.size).or_default() += 1;
+    }
+
+    let mut res = vec![None; files.len()];
+    let mut originals: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (index, (path, _, file)) in files.iter().enumerate() {
+        if size_counts[&file.size] < 2 || unique_paths.contains(path) {
+            continue;
+        }
+
+        let candidates = originals
+            .entry((file.size, file.get_content_hash()?))
+            .or_default();
+        for &original in candidates.iter() {
+            if files[original].2.has_same_content(file)? {
+                res[index] = Some(original);
+                break;
+            }
+        }
+
+        if res[index].is_none() {
+            candidates.push(index);
+        }
+    }
+
+    Ok(res)
 }
 
-fn fill_boot_catalog(
+/// Place the file contents, giving back the space saved by deduplication in
+/// bytes
+fn reserve_file_space(
     tree: &mut DirectoryEntry,
+    current_lba: &mut u32,
     opt: &option::Opt,
-) -> std::io::Result<()> {
-    let value = opt.eltorito_opt.eltorito_boot.clone().unwrap();
-    let eltorito_boot_file: &mut FileEntry = tree.get_file(&value).unwrap();
-    let sector_count = (opt.boot_load_size * SECTOR_SIZE) / LOGIC_SIZE_U32;
+) -> Result<u64> {
+    let mut files = Vec::new();
+    collect_content_files(tree, "", &mut files);
+
+    let mut files: Vec<_> = files
+        .into_iter()
+        .map(|(path, file)| {
+            let rule = opt.sort_rules.iter().find(|rule| rule.matches(&path));
+            (path, rule, file)
+        })
+        .collect();
+    // Heaviest first, the sort is stable so the tree order is kept between
+    // files of the same weight
+    files.sort_by_key(|(_, rule, _)| {
+        std::cmp::Reverse(rule.map_or(0, |rule| rule.weight))
+    });
+
+    // The first file with a given content owns the extent, its duplicates
+    // point to it
+    let duplicates = if opt.deduplicate {
+        find_duplicates(&files, opt)?
+    } else {
+        vec![None; files.len()]
+    };
 
-    let eltorito_lba = eltorito_boot_file.lba;
+    let mut saved_size = 0;
+    for index in 0..files.len() {
+        let lba_count =
+            u32::try_from((files[index].2.size + LOGIC_SIZE) / LOGIC_SIZE)
+                .map_err(|_| IsoError::ImageTooLarge)?;
+
+        if let Some(original) = duplicates[index] {
+            let lba = files[original].2.lba;
+            let child_file = &mut files[index].2;
+            child_file.lba = lba;
+            child_file.duplicate = true;
+            saved_size += u64::from(lba_count) * u64::from(LOGIC_SIZE_U32);
+            continue;
+        }
 
-    let file: &mut FileEntry = tree.get_file("boot.catalog").unwrap();
+        let (_, rule, child_file) = &mut files[index];
+        let alignment = rule.map_or(1, |rule| rule.alignment_in_lb.max(1));
+        child_file.lba = current_lba
+            .checked_next_multiple_of(alignment)
+            .ok_or(IsoError::ImageTooLarge)?;
+        *current_lba = child_file
+            .lba
+            .checked_add(lba_count)
+            .ok_or(IsoError::ImageTooLarge)?;
+    }
 
-    let mut buff: Vec<u8> = Vec::new();
+    Ok(saved_size)
+}
 
-    // Validation Header
+const DEFAULT_PRIMARY_NAME: &[u8] = b"ISOIMAGE                        ";
+fn generate_volume_descriptors(opt: &option::Opt) -> Vec<VolumeDescriptor> {
+    let mut res: Vec<VolumeDescriptor> = Vec::new();
 
-    // Header ID
-    buff.write_u8(0x1)?;
+    let volume_name: Cow<'static, [u8]> = opt
+        .primary_volume_name
+        .clone()
+        .map_or(DEFAULT_PRIMARY_NAME.into(), |x| x.into_bytes().into());
 
-    // Plateform ID (0x0 = 80x86, 0x1 = PowerPC, 0x2 = Mac, 0xef = EFI)
-    // TODO: UEFI?
-    buff.write_u8(0x0)?;
+    res.push(VolumeDescriptor::Primary(volume_name.clone()));
+    if opt.eltorito_opt.has_boot_catalog() {
+        res.push(VolumeDescriptor::Boot);
+    }
+    if opt.joliet {
+        res.push(VolumeDescriptor::Supplementary(volume_name));
+    }
+    res.push(VolumeDescriptor::End);
 
-    // Reserved
-    buff.write_u16::<LittleEndian>(0x0)?;
+    res
+}
 
-    let id_str: [u8; 0x16] = [0x0; 0x16];
-    buff.write_all(&id_str)?;
+/// Add the checksums manifest, sized with placeholder digests until the
+/// files are written
+fn create_manifest(tree: &mut DirectoryEntry) -> Result<()> {
+    // The manifest of a previous session is replaced, only the files written
+    // by this one are listed
+    tree.files_childs.retain(|x| {
+        !x.is_imported() || x.get_file_name() != checksum::MANIFEST_NAME
+    });
+    if tree.has_file(checksum::MANIFEST_NAME) {
+        return Err(IsoError::AlreadyExists(
+            checksum::MANIFEST_NAME.to_string(),
+        ));
+    }
 
-    // FIXME: actually calculate the checksum correctly!
-    buff.write_u32::<LittleEndian>(0x55aa_0000)?;
+    let data = checksum::get_manifest(tree);
+    let size = data.len();
+    let file_type = FileType::Buffer {
+        name: checksum::MANIFEST_NAME.to_string(),
+        data,
+    };
+    tree.add_file(FileEntry::new(file_type, size));
 
-    buff.write_u8(0x55)?;
-    buff.write_u8(0xAA)?;
+    Ok(())
+}
+
+/// Write the checksums manifest once the digests of the files are known
+fn write_manifest<W>(tree: &mut DirectoryEntry, out: &mut W) -> Result<()>
+where
+    W: Write + Seek,
+{
+    let data = checksum::get_manifest(tree);
+    let file = tree.get_file(checksum::MANIFEST_NAME).unwrap();
 
-    let boot_indicator = if opt.eltorito_opt.no_boot { 0x0 } else { 0x88 };
+    // Only the digests should have changed since it was sized
+    if data.len() != file.size {
+        return Err(IsoError::ManifestSizeChanged);
+    }
+    file.file_type = FileType::Buffer {
+        name: checksum::MANIFEST_NAME.to_string(),
+        data,
+    };
+    file.write_content(out, false)
+}
+
+fn create_boot_catalog(tree: &mut DirectoryEntry) {
+    let catalog_file = FileEntry::new_buffered(String::from("boot.catalog"));
+    tree.add_file(catalog_file);
+}
+
+/// Write a no emulation initial/default or section entry of the boot catalog
+fn write_boot_entry(
+    buff: &mut Vec<u8>,
+    bootable: bool,
+    sector_count: u16,
+    lba: u32,
+) -> Result<()> {
+    let boot_indicator = if bootable { 0x88 } else { 0x0 };
 
     buff.write_u8(boot_indicator)?;
 
@@ -147,15 +422,132 @@ fn fill_boot_catalog(
     buff.write_u8(0x0)?;
 
     // Sector count
-    buff.write_u16::<LittleEndian>(sector_count.try_into().unwrap())?;
+    buff.write_u16::<LittleEndian>(sector_count)?;
 
     // LBA of the file
-    buff.write_u32::<LittleEndian>(eltorito_lba)?;
+    buff.write_u32::<LittleEndian>(lba)?;
 
+    // Unused for the default entry, no selection criteria for the section
+    // entries
     let unused: [u8; 0x14] = [0x0; 0x14];
-    // Unused
     buff.write_all(&unused)?;
 
+    Ok(())
+}
+
+/// Number of 512 bytes virtual sectors loaded by a boot entry (El Torito
+/// 2.2), `load_size` or the whole boot image when 0
+fn get_boot_sector_count(boot_file: &FileEntry, load_size: u32) -> u16 {
+    let sector_count = match load_size {
+        0 => boot_file
+            .size
+            .div_ceil(usize::try_from(SECTOR_SIZE).unwrap())
+            .try_into()
+            .unwrap_or(u32::MAX),
+        load_size => load_size,
+    };
+
+    sector_count.try_into().unwrap_or(u16::MAX)
+}
+
+fn fill_boot_catalog(
+    tree: &mut DirectoryEntry,
+    opt: &option::Opt,
+) -> Result<()> {
+    // (platform, bootable, sector count, LBA) of every entry, the first one
+    // being the default entry
+    let mut entries: Vec<(option::Platform, bool, u16, u32)> = Vec::new();
+
+    if let Some(value) = &opt.eltorito_opt.eltorito_boot {
+        let eltorito_boot_file: &mut FileEntry = tree
+            .get_file(value)
+            .ok_or_else(|| IsoError::BootFileMissing(value.clone()))?;
+
+        entries.push((
+            option::Platform::X86,
+            !opt.eltorito_opt.no_boot,
+            get_boot_sector_count(eltorito_boot_file, opt.boot_load_size),
+            eltorito_boot_file.lba,
+        ));
+    }
+
+    for entry in &opt.eltorito_opt.entries {
+        let boot_file: &mut FileEntry =
+            tree.get_file(&entry.boot_file).ok_or_else(|| {
+                IsoError::BootFileMissing(entry.boot_file.clone())
+            })?;
+
+        entries.push((
+            entry.platform,
+            !entry.no_boot,
+            get_boot_sector_count(boot_file, entry.load_size.into()),
+            boot_file.lba,
+        ));
+    }
+
+    let file: &mut FileEntry = tree.get_file("boot.catalog").unwrap();
+
+    let mut buff: Vec<u8> = Vec::new();
+
+    // Validation Header
+
+    // Header ID
+    buff.write_u8(0x1)?;
+
+    // Plateform ID (0x0 = 80x86, 0x1 = PowerPC, 0x2 = Mac, 0xef = EFI)
+    buff.write_u8(entries[0].0.id())?;
+
+    // Reserved
+    buff.write_u16::<LittleEndian>(0x0)?;
+
+    let id_str: [u8; 0x18] = [0x0; 0x18];
+    buff.write_all(&id_str)?;
+
+    // Checksum, filled once the entry is complete
+    buff.write_u16::<LittleEndian>(0x0)?;
+
+    buff.write_u8(0x55)?;
+    buff.write_u8(0xAA)?;
+
+    // The words of the validation entry must sum up to 0
+    let checksum = utils::eltorito_checksum(&buff).wrapping_neg();
+    LittleEndian::write_u16(&mut buff[0x1C..], checksum);
+
+    // Initial/Default Entry
+    let (_, bootable, sector_count, lba) = entries[0];
+    write_boot_entry(&mut buff, bootable, sector_count, lba)?;
+
+    // Section headers, followed by their entries
+    let sections: Vec<_> = entries[1..].chunk_by(|a, b| a.0 == b.0).collect();
+    for (index, section) in sections.iter().enumerate() {
+        // Header indicator (0x91 = final header)
+        let header_indicator = if index + 1 == sections.len() {
+            0x91
+        } else {
+            0x90
+        };
+        buff.write_u8(header_indicator)?;
+
+        // Platform ID
+        buff.write_u8(section[0].0.id())?;
+
+        // Number of section entries
+        buff.write_u16::<LittleEndian>(section.len().try_into().unwrap())?;
+
+        // ID string
+        let id_str: [u8; 0x1C] = [0x0; 0x1C];
+        buff.write_all(&id_str)?;
+
+        for &(_, bootable, sector_count, lba) in *section {
+            write_boot_entry(&mut buff, bootable, sector_count, lba)?;
+        }
+    }
+
+    // Only one logical block was reserved for the catalog
+    if buff.len() > LOGIC_SIZE {
+        return Err(IsoError::TooManyBootEntries);
+    }
+
     file.file_type = match &file.file_type {
         FileType::Buffer { name, .. } => FileType::Buffer {
             name: name.clone(),
@@ -171,14 +563,19 @@ fn fill_boot_catalog(
 fn patch_boot_image(
     tree: &mut DirectoryEntry,
     opt: &option::Opt,
-) -> std::io::Result<()> {
+) -> Result<()> {
     let value = opt.eltorito_opt.eltorito_boot.clone().unwrap();
-    let file: &mut FileEntry = tree.get_file(&value).unwrap();
+    let file: &mut FileEntry = tree
+        .get_file(&value)
+        .ok_or_else(|| IsoError::BootFileMissing(value.clone()))?;
 
     // We need to copy the file to a buffer and change the file type internally to be able to patch it
-    let mut content: Box<dyn Read> = file.open_content_provider();
+    let mut content: Box<dyn Read> = file
+        .open_content_provider()
+        .map_err(|err| file.error_context(err))?;
     let mut buff: Cursor<Vec<u8>> = Cursor::new(Vec::new());
     std::io::copy(&mut content, &mut buff)?;
+    drop(content);
 
     if opt.eltorito_opt.boot_info_table {
         // Patch the content now
@@ -193,9 +590,12 @@ fn patch_boot_image(
         // Length of boot file.
         buff.write_u32::<LittleEndian>(file.size.try_into().unwrap())?;
 
-        // Checksum (actually ignored by GRUB2)
-        // FIXME: should we implement it?
-        buff.write_u32::<LittleEndian>(0x0)?;
+        // Checksum of the image after the boot info table
+        let checksum = utils::boot_info_table_checksum(buff.get_ref());
+        buff.write_u32::<LittleEndian>(checksum)?;
+
+        // Reserved
+        buff.write_all(&[0x0; 0x28])?;
     }
 
     if opt.eltorito_opt.grub2_boot_info {
@@ -218,7 +618,7 @@ fn write_system_area<T>(
     output_writter: &mut T,
     opt: &option::Opt,
     lb_count: u32,
-) -> std::io::Result<()>
+) -> Result<()>
 where
     T: Write + Seek,
 {
@@ -238,15 +638,16 @@ where
     }
 
     if let Some(embedded_boot) = embedded_boot {
-        let path: PathBuf = PathBuf::from_str(&embedded_boot).unwrap();
-        if path.metadata().unwrap().len()
-            > (LOGIC_SIZE * 0x10).try_into().unwrap()
-        {
-            return Err(std::io::Error::other(
-                "generic boot file is bigger than 32768 bytes!",
-            ));
+        let path: PathBuf = PathBuf::from(embedded_boot);
+        let size = path
+            .metadata()
+            .map_err(|err| IsoError::host_io(&path, err))?
+            .len();
+        if size > (LOGIC_SIZE * 0x10).try_into().unwrap() {
+            return Err(IsoError::EmbeddedBootTooLarge { path, size });
         }
-        let mut embedded_boot_file = File::open(path)?;
+        let mut embedded_boot_file =
+            File::open(&path).map_err(|err| IsoError::host_io(&path, err))?;
         std::io::copy(&mut embedded_boot_file, output_writter)?;
     }
 
@@ -256,7 +657,9 @@ where
         && let Some(boot) = &opt.eltorito_opt.eltorito_boot
     {
         output_writter.seek(SeekFrom::Start(old_pos + 0x1B0))?;
-        let file: &mut FileEntry = tree.get_file(boot).unwrap();
+        let file: &mut FileEntry = tree
+            .get_file(boot)
+            .ok_or_else(|| IsoError::BootFileMissing(boot.clone()))?;
         output_writter
             .write_u64::<LittleEndian>(u64::from(file.lba * 4 + 4))?;
         // Go back to where we are supposed to be...
@@ -272,7 +675,7 @@ where
         output_writter.write_all(&padding)?;
     }
 
-    if opt.protective_msdos_label {
+    if opt.protective_msdos_label && !opt.gpt {
         let current_pos = output_writter.stream_position()?;
 
         // First MBR partition
@@ -286,7 +689,7 @@ where
         let head_count = 64;
         let sector_count = 32;
 
-        let size_in_sector = (lb_count * LOGIC_SIZE_U32) / SECTOR_SIZE;
+        let size_in_sector = lb_count * (LOGIC_SIZE_U32 / SECTOR_SIZE);
 
         // CHS address start
         utils::write_lba_to_cls(
@@ -332,74 +735,402 @@ where
     Ok(())
 }
 
-pub fn create_iso(opt: &option::Opt) -> std::io::Result<Vec<u8>> {
-    let volume_descriptor_list = generate_volume_descriptors(opt);
+/// Size of the backup GPT (partition entries and header) at the end of the
+/// image, in logical blocks
+const GPT_BACKUP_SIZE_IN_LB: u32 = 9;
+const GPT_ENTRY_COUNT: u32 = 128;
+const GPT_ENTRY_SIZE: u32 = 128;
+/// Sectors used by the partition entries
+const GPT_ENTRIES_SIZE_IN_SECTOR: u32 =
+    GPT_ENTRY_COUNT * GPT_ENTRY_SIZE / SECTOR_SIZE;
+
+/// Microsoft basic data partition type, EBD0A0A2-B9E5-4433-87C0-68B6B72699C7
+const GPT_BASIC_DATA_GUID: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6,
+    0xB7, 0x26, 0x99, 0xC7,
+];
+/// EFI system partition type, C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+const GPT_EFI_SYSTEM_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0,
+    0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+/// Random looking (version 4) GUID derived from `seed`, to keep images
+/// reproducible
+fn gpt_guid(seed: &[u8], index: u8) -> [u8; 16] {
+    let mut res = [0u8; 16];
+    for (i, chunk) in res.chunks_exact_mut(4).enumerate() {
+        let mut data = seed.to_vec();
+        data.push(index);
+        data.push(u8::try_from(i).unwrap());
+        chunk.copy_from_slice(&utils::crc32(&data).to_le_bytes());
+    }
+
+    res[7] = (res[7] & 0x0F) | 0x40;
+    res[8] = (res[8] & 0x3F) | 0x80;
+    res
+}
+
+fn write_gpt_header<T>(
+    output_writter: &mut T,
+    current_lba: u64,
+    backup_lba: u64,
+    entries_lba: u64,
+    disk_guid: &[u8; 16],
+    entries_crc: u32,
+) -> Result<()>
+where
+    T: Write + Seek,
+{
+    let sector_count = current_lba.max(backup_lba) + 1;
+    let entries_size_in_sector = u64::from(GPT_ENTRIES_SIZE_IN_SECTOR);
+
+    let mut header: Vec<u8> = Vec::new();
+    header.write_all(b"EFI PART")?;
+
+    // Revision 1.0
+    header.write_u32::<LittleEndian>(0x0001_0000)?;
+
+    // Header size
+    header.write_u32::<LittleEndian>(0x5C)?;
+
+    // Header CRC32, filled once the header is complete
+    header.write_u32::<LittleEndian>(0x0)?;
+
+    // Reserved
+    header.write_u32::<LittleEndian>(0x0)?;
+
+    header.write_u64::<LittleEndian>(current_lba)?;
+    header.write_u64::<LittleEndian>(backup_lba)?;
+
+    // First and last usable LBA, outside of both partition entry arrays
+    header.write_u64::<LittleEndian>(2 + entries_size_in_sector)?;
+    header
+        .write_u64::<LittleEndian>(sector_count - 2 - entries_size_in_sector)?;
+
+    header.write_all(disk_guid)?;
+    header.write_u64::<LittleEndian>(entries_lba)?;
+    header.write_u32::<LittleEndian>(GPT_ENTRY_COUNT)?;
+    header.write_u32::<LittleEndian>(GPT_ENTRY_SIZE)?;
+    header.write_u32::<LittleEndian>(entries_crc)?;
+
+    let header_crc = utils::crc32(&header);
+    LittleEndian::write_u32(&mut header[0x10..], header_crc);
+
+    // Rest of the sector is reserved
+    header.resize(usize::try_from(SECTOR_SIZE).unwrap(), 0x0);
+
+    output_writter
+        .seek(SeekFrom::Start(current_lba * u64::from(SECTOR_SIZE)))?;
+    output_writter.write_all(&header)?;
+
+    Ok(())
+}
+
+/// Write a protective MBR, the GPT with a partition for the ISO 9660 data and
+/// one for the EFI boot image (if any) and the backup GPT in the last
+/// `GPT_BACKUP_SIZE_IN_LB` logical blocks of the image
+fn write_gpt<T>(
+    tree: &mut DirectoryEntry,
+    output_writter: &mut T,
+    opt: &option::Opt,
+    lb_count: u32,
+    build_time: DateTime<Utc>,
+) -> Result<()>
+where
+    T: Write + Seek,
+{
+    let old_pos = output_writter.stream_position()?;
+
+    let sector_count =
+        u64::from(lb_count) * u64::from(LOGIC_SIZE_U32 / SECTOR_SIZE);
+    let backup_header_lba = sector_count - 1;
+    let backup_entries_lba =
+        backup_header_lba - u64::from(GPT_ENTRIES_SIZE_IN_SECTOR);
+
+    // GUIDs only depend on the volume and the build date
+    let mut seed = opt
+        .primary_volume_name
+        .clone()
+        .unwrap_or_default()
+        .into_bytes();
+    seed.extend(build_time.timestamp().to_le_bytes());
+    let disk_guid = gpt_guid(&seed, 0);
+
+    // (type, first LBA, last LBA, name) of the partitions
+    let mut partitions: Vec<([u8; 16], u64, u64, &str)> = Vec::new();
+
+    // ISO 9660 data, from the volume descriptors to the backup GPT
+    partitions.push((
+        GPT_BASIC_DATA_GUID,
+        u64::from(0x10 * LOGIC_SIZE_U32 / SECTOR_SIZE),
+        u64::from(lb_count - GPT_BACKUP_SIZE_IN_LB)
+            * u64::from(LOGIC_SIZE_U32 / SECTOR_SIZE)
+            - 1,
+        "ISO9660",
+    ));
+
+    let efi_entry = opt
+        .eltorito_opt
+        .entries
+        .iter()
+        .find(|x| x.platform == option::Platform::Efi);
+    if let Some(efi_entry) = efi_entry {
+        let file: &mut FileEntry =
+            tree.get_file(&efi_entry.boot_file).ok_or_else(|| {
+                IsoError::BootFileMissing(efi_entry.boot_file.clone())
+            })?;
+        let first_lba =
+            u64::from(file.lba) * u64::from(LOGIC_SIZE_U32 / SECTOR_SIZE);
+        let size_in_sector = u64::try_from(file.size)
+            .unwrap()
+            .div_ceil(u64::from(SECTOR_SIZE))
+            .max(1);
+
+        partitions.push((
+            GPT_EFI_SYSTEM_GUID,
+            first_lba,
+            first_lba + size_in_sector - 1,
+            "EFI boot image",
+        ));
+    }
+
+    let mut entries: Vec<u8> = Vec::new();
+    for (index, (type_guid, first_lba, last_lba, name)) in
+        partitions.into_iter().enumerate()
+    {
+        entries.write_all(&type_guid)?;
+        entries
+            .write_all(&gpt_guid(&seed, u8::try_from(index + 1).unwrap()))?;
+        entries.write_u64::<LittleEndian>(first_lba)?;
+        entries.write_u64::<LittleEndian>(last_lba)?;
+
+        // Attributes
+        entries.write_u64::<LittleEndian>(0x0)?;
+
+        // Name (UTF-16LE)
+        let mut name_buff = [0u8; 72];
+        for (dst, c) in name_buff.chunks_exact_mut(2).zip(name.encode_utf16()) {
+            dst.copy_from_slice(&c.to_le_bytes());
+        }
+        entries.write_all(&name_buff)?;
+    }
+    entries.resize(
+        usize::try_from(GPT_ENTRY_COUNT * GPT_ENTRY_SIZE).unwrap(),
+        0x0,
+    );
+    let entries_crc = utils::crc32(&entries);
+
+    // Protective MBR partition (UEFI 5.2.3), covering the whole disk
+    output_writter.seek(SeekFrom::Start(0x1BE))?;
+    output_writter.write_u8(0x0)?;
+    output_writter.write_all(&[0x00, 0x02, 0x00])?;
+    output_writter.write_u8(0xEE)?;
+    output_writter.write_all(&[0xFF, 0xFF, 0xFF])?;
+    output_writter.write_u32::<LittleEndian>(1)?;
+    output_writter.write_u32::<LittleEndian>(
+        u32::try_from(sector_count - 1).unwrap_or(u32::MAX),
+    )?;
+
+    // Clean other boot entries
+    let empty_data: [u8; 0x10] = [0x0; 0x10];
+    output_writter.write_all(&empty_data)?;
+    output_writter.write_all(&empty_data)?;
+    output_writter.write_all(&empty_data)?;
+
+    // write "valid bootsector"
+    output_writter.write_u8(0x55)?;
+    output_writter.write_u8(0xAA)?;
+
+    // Primary GPT, just after the MBR
+    write_gpt_header(
+        output_writter,
+        1,
+        backup_header_lba,
+        2,
+        &disk_guid,
+        entries_crc,
+    )?;
+    output_writter.write_all(&entries)?;
+
+    // Backup GPT, at the end of the image
+    output_writter
+        .seek(SeekFrom::Start(backup_entries_lba * u64::from(SECTOR_SIZE)))?;
+    output_writter.write_all(&entries)?;
+    write_gpt_header(
+        output_writter,
+        backup_header_lba,
+        1,
+        backup_entries_lba,
+        &disk_guid,
+        entries_crc,
+    )?;
+
+    output_writter.seek(SeekFrom::Start(old_pos))?;
 
+    Ok(())
+}
+
+pub fn create_iso(opt: &option::Opt) -> Result<Vec<u8>> {
     let mut out = Cursor::new(Vec::new());
+    create_iso_with_checksums_to(opt, &mut out)?;
 
-    let mut current_lba: u32 =
-        0x10 + 1 + u32::try_from(volume_descriptor_list.len()).unwrap();
+    Ok(out.into_inner())
+}
 
-    let path_table_start_lba = current_lba;
+/// Summary of a written image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteReport {
+    /// Size of the image in logical blocks
+    pub size_in_lb: u32,
+    /// Space saved by the files sharing the extent of an identical file, in
+    /// bytes
+    pub deduplicated_size: u64,
+}
 
-    // Reserve 4 LBA for path tables (add some spacing after table)
-    current_lba += 4;
+/// Write the image directly to `out`, without buffering it in memory.
+///
+/// Everything is written at absolute offsets, `out` is expected to start at
+/// position 0. `opt.checksums` needs [create_iso_with_checksums_to].
+pub fn create_iso_to<W>(opt: &option::Opt, out: &mut W) -> Result<WriteReport>
+where
+    W: Write + Seek,
+{
+    IsoBuilder::new().write_to(opt, out)
+}
 
-    let mut tree = DirectoryEntry::new()?;
+/// Same as [create_iso_to], the image is then read back to implant its
+/// digests when `opt.checksums` is set
+pub fn create_iso_with_checksums_to<F>(
+    opt: &option::Opt,
+    out: &mut F,
+) -> Result<WriteReport>
+where
+    F: Read + Write + Seek,
+{
+    IsoBuilder::new().write_to_with_checksums(opt, out)
+}
 
-    if opt.eltorito_opt.eltorito_boot.is_some() {
+/// Write the image of `tree` to `out`, as a session starting at
+/// `session_lba` when appending to an existing image
+fn write_iso<W>(
+    mut tree: DirectoryEntry,
+    opt: &option::Opt,
+    session_lba: u32,
+    out: &mut W,
+) -> Result<WriteReport>
+where
+    W: Write + Seek,
+{
+    opt.volume_opt.validate()?;
+    let volume_descriptor_list = generate_volume_descriptors(opt);
+    let build_time = opt.timestamp.build_time()?;
+
+    let mut current_lba: u32 = session_lba
+        + 0x10
+        + 1
+        + u32::try_from(volume_descriptor_list.len()).unwrap();
+
+    if opt.eltorito_opt.has_boot_catalog() {
         create_boot_catalog(&mut tree);
     }
 
-    tree.set_path(&opt.input_files)?;
-    let mut path_table_index = 0;
+    if !opt.input_files.is_empty() {
+        let mut input_tree = DirectoryEntry::new()?;
+        input_tree.set_path(&opt.input_files, &opt.host_filter)?;
+        tree.merge(input_tree);
+    }
+    tree.drop_replaced_imports();
+
+    if opt.checksums {
+        create_manifest(&mut tree)?;
+    }
+
+    tree.mark_files("", &opt.hidden_files, &opt.associated_files);
+
+    tree.set_timestamp(
+        build_time,
+        matches!(opt.timestamp, option::Timestamp::FileModified),
+    )?;
+    tree.relocate_deep_directories(build_time);
+    tree.assign_identifiers(opt.interchange_level);
+    compress_files(&mut tree, opt)?;
+    tree.check_names()?;
+    tree.check_file_sizes(opt.interchange_level)?;
+    tree.check_directory_count()?;
+    assign_path_table_indexes(&mut tree);
+    tree.assign_serials(&mut 1);
+
+    if opt.normalize_attributes {
+        tree.normalize_attributes();
+    }
+
+    // Both path tables (little and big endian) are sized after the tree
+    let path_table_start_lba = current_lba;
+    current_lba += 2 * tree.get_path_table_size_in_lb(Hierarchy::Primary);
+
+    // Same for the Joliet path tables
+    let joliet_path_table_start_lba = current_lba;
+    if opt.joliet {
+        current_lba += 2 * tree.get_path_table_size_in_lb(Hierarchy::Joliet);
+    }
 
     let mut tmp_lba = current_lba;
 
-    // create 'ER' entry of Rock Ridge 1.2
-    let mut continuation_area: Vec<u8> = Vec::new();
-    continuation_area.write_all(b"ER")?;
-    continuation_area.write_u8(0xB6)?;
-    continuation_area.write_u8(0x1)?;
-    continuation_area.write_u8(0x9)?;
-    continuation_area.write_u8(0x48)?;
-    continuation_area.write_u8(0x5d)?;
-    continuation_area.write_u8(0x1)?;
-    continuation_area.write_all(b"IEEE_1282")?;
-    continuation_area
-        .write_all(b"THE IEEE 1282 PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS.")?;
-    continuation_area.write_all(b"PLEASE CONTACT THE IEEE STANDARDS DEPARTMENT, PISCATAWAY, NJ, USA FOR THE 1282 SPECIFICATION.")?;
-    tree.continuation_area = Some(continuation_area);
-
-    assign_directory_identifiers(
-        &mut tree,
-        &mut path_table_index,
-        &mut tmp_lba,
-    );
-    tree.parent_index = 1;
-    tree.lba = current_lba;
+    assign_directory_lba(&mut tree, &mut tmp_lba);
+    resolve_relocations(&mut tree);
+
+    if opt.joliet {
+        assign_joliet_directory_lba(&mut tree, &mut tmp_lba);
+    }
 
     current_lba = tmp_lba;
     current_lba += 1;
 
-    reserve_file_space(&mut tree, &mut current_lba);
+    let deduplicated_size =
+        reserve_file_space(&mut tree, &mut current_lba, opt)?;
+
+    if opt.gpt {
+        current_lba += GPT_BACKUP_SIZE_IN_LB;
+    }
 
-    if opt.eltorito_opt.eltorito_boot.is_some() {
+    if opt.eltorito_opt.has_boot_catalog() {
         fill_boot_catalog(&mut tree, opt)?;
     }
 
-    if opt.eltorito_opt.boot_info_table || opt.eltorito_opt.grub2_boot_info {
+    if opt.eltorito_opt.eltorito_boot.is_some()
+        && (opt.eltorito_opt.boot_info_table
+            || opt.eltorito_opt.grub2_boot_info)
+    {
         patch_boot_image(&mut tree, opt)?;
     }
 
-    write_system_area(&mut tree, &mut out, opt, current_lba)?;
+    if session_lba == 0 {
+        write_system_area(&mut tree, out, opt, current_lba)?;
+    } else {
+        // The system area belongs to the first session
+        out.seek(SeekFrom::Start(
+            u64::from(session_lba + 0x10) * u64::from(LOGIC_SIZE_U32),
+        ))?;
+    }
+
+    if opt.gpt {
+        write_gpt(&mut tree, out, opt, current_lba, build_time)?;
+    }
 
     for mut volume in volume_descriptor_list {
+        let volume_path_table_start_lba = match volume {
+            VolumeDescriptor::Supplementary(_) => joliet_path_table_start_lba,
+            _ => path_table_start_lba,
+        };
+
         volume.write_volume(
-            &mut out,
+            out,
             &mut tree,
-            path_table_start_lba,
+            volume_path_table_start_lba,
             current_lba,
+            build_time,
+            &opt.volume_opt,
         )?;
     }
 
@@ -408,10 +1139,67 @@ pub fn create_iso(opt: &option::Opt) -> std::io::Result<Vec<u8>> {
     out.write_all(b"MKI ")?;
     out.write_all(&empty_mki_section)?;
 
-    tree.write_path_table::<_, LittleEndian>(&mut out, path_table_start_lba)?;
-    tree.write_path_table::<_, BigEndian>(&mut out, path_table_start_lba + 1)?;
-    tree.write_extent(&mut out, None)?;
-    tree.write_files(&mut out)?;
+    // Joliet reaches the relocated directories through their placeholders
+    let mut relocated = HashMap::new();
+    tree.collect_relocated_directories(&mut relocated);
+
+    tree.write_path_table::<_, LittleEndian>(
+        out,
+        path_table_start_lba,
+        Hierarchy::Primary,
+        &relocated,
+    )?;
+    tree.write_path_table::<_, BigEndian>(
+        out,
+        path_table_start_lba
+            + tree.get_path_table_size_in_lb(Hierarchy::Primary),
+        Hierarchy::Primary,
+        &relocated,
+    )?;
+    tree.write_extent(out, None, Hierarchy::Primary, &relocated)?;
+
+    if opt.joliet {
+        tree.write_path_table::<_, LittleEndian>(
+            out,
+            joliet_path_table_start_lba,
+            Hierarchy::Joliet,
+            &relocated,
+        )?;
+        tree.write_path_table::<_, BigEndian>(
+            out,
+            joliet_path_table_start_lba
+                + tree.get_path_table_size_in_lb(Hierarchy::Joliet),
+            Hierarchy::Joliet,
+            &relocated,
+        )?;
+        tree.write_extent(out, None, Hierarchy::Joliet, &relocated)?;
+    }
+
+    tree.write_files(out, opt.checksums)?;
+    if opt.checksums {
+        write_manifest(&mut tree, out)?;
+    }
 
-    Ok(out.into_inner())
+    // Make sure the output covers the whole volume, even if the last reserved
+    // blocks were never written
+    let image_size = u64::from(current_lba) * u64::from(LOGIC_SIZE_U32);
+    let written_size = out.seek(SeekFrom::End(0))?;
+    if written_size < image_size {
+        std::io::copy(
+            &mut std::io::repeat(0).take(image_size - written_size),
+            out,
+        )?;
+    }
+
+    Ok(WriteReport {
+        size_in_lb: current_lba,
+        deduplicated_size,
+    })
+}
+
+pub fn read_iso<R>(reader: R) -> std::io::Result<IsoImage<R>>
+where
+    R: Read + Seek,
+{
+    IsoImage::open(reader)
 }
@@ -1,19 +1,340 @@
+use crate::error::{IsoError, Result};
+
+use chrono::prelude::*;
+
 use std::path::PathBuf;
 
+/// Source of the dates recorded in the image
+#[derive(Debug, Clone, Default)]
+pub enum Timestamp {
+    /// Time of the build
+    #[default]
+    Now,
+    /// Fixed date, identical inputs will give byte-identical images
+    Fixed(DateTime<Utc>),
+    /// Date from the `SOURCE_DATE_EPOCH` environment variable, time of the
+    /// build if it isn't set
+    SourceDateEpoch,
+    /// Modification time of every mirrored file and directory, other entries
+    /// and the volume descriptors use the `SOURCE_DATE_EPOCH` date
+    FileModified,
+}
+
+impl Timestamp {
+    /// Date of the volume descriptors and of the generated entries
+    pub fn build_time(&self) -> Result<DateTime<Utc>> {
+        match self {
+            Timestamp::Now => Ok(Utc::now()),
+            Timestamp::Fixed(value) => Ok(*value),
+            Timestamp::SourceDateEpoch | Timestamp::FileModified => {
+                match std::env::var("SOURCE_DATE_EPOCH") {
+                    Ok(value) => value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .and_then(|x| DateTime::from_timestamp(x, 0))
+                        .ok_or(IsoError::InvalidSourceDateEpoch(value)),
+                    Err(_) => Ok(Utc::now()),
+                }
+            }
+        }
+    }
+}
+
+/// ISO 9660 interchange level, restricting the primary hierarchy identifiers
+/// (ECMA-119 10)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InterchangeLevel {
+    /// 8.3 file names and 8 characters directory names
+    #[default]
+    Level1,
+    /// Names up to 30 characters for files and 31 for directories
+    Level2,
+    /// Same names as level 2, files bigger than 4 GiB span multiple extents
+    Level3,
+}
+
+/// Character set of a volume descriptor identifier (ECMA-119 7.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharacterSet {
+    A,
+    D,
+    /// d-characters with the '.' and ';' separators of file identifiers
+    File,
+}
+
+impl CharacterSet {
+    fn contains(self, c: char) -> bool {
+        match self {
+            CharacterSet::A => is_a_character(c),
+            CharacterSet::D => is_d_character(c),
+            CharacterSet::File => is_d_character(c) || c == '.' || c == ';',
+        }
+    }
+}
+
+pub(crate) fn is_d_character(c: char) -> bool {
+    matches!(c, 'A'..='Z' | '0'..='9' | '_')
+}
+
+fn is_a_character(c: char) -> bool {
+    is_d_character(c) || " !\"%&'()*+,-./:;<=>?".contains(c)
+}
+
+/// Uppercase `value` and replace everything that isn't an a-character by an
+/// underscore, to fill the a-characters identifiers of `VolumeOpt`
+pub fn to_a_characters(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| c.to_ascii_uppercase())
+        .map(|c| if is_a_character(c) { c } else { '_' })
+        .collect()
+}
+
+/// Identifiers and dates of the volume descriptors (ECMA-119 8.4), blank or
+/// unspecified when not set
+#[derive(Debug, Clone, Default)]
+pub struct VolumeOpt {
+    /// System that can use the system area, a-characters (32)
+    pub system_identifier: Option<String>,
+    /// d-characters (128)
+    pub volume_set_identifier: Option<String>,
+    /// a-characters (128)
+    pub publisher_identifier: Option<String>,
+    /// a-characters (128)
+    pub data_preparer_identifier: Option<String>,
+    /// a-characters (128)
+    pub application_identifier: Option<String>,
+    /// Name of a file of the root directory (37)
+    pub copyright_file_identifier: Option<String>,
+    /// Name of a file of the root directory (37)
+    pub abstract_file_identifier: Option<String>,
+    /// Name of a file of the root directory (37)
+    pub bibliographic_file_identifier: Option<String>,
+    /// Date of the last modification, the creation date if not set
+    pub modification_time: Option<DateTime<Utc>>,
+    /// Date after which the volume is obsolete, never if not set
+    pub expiration_time: Option<DateTime<Utc>>,
+    /// Date after which the volume may be used, right away if not set
+    pub effective_time: Option<DateTime<Utc>>,
+}
+
+impl VolumeOpt {
+    /// Make sure that every identifier only uses its character set and fits
+    /// in its field
+    pub fn validate(&self) -> Result<()> {
+        let identifiers = [
+            ("system", &self.system_identifier, CharacterSet::A, 32),
+            (
+                "volume set",
+                &self.volume_set_identifier,
+                CharacterSet::D,
+                128,
+            ),
+            (
+                "publisher",
+                &self.publisher_identifier,
+                CharacterSet::A,
+                128,
+            ),
+            (
+                "data preparer",
+                &self.data_preparer_identifier,
+                CharacterSet::A,
+                128,
+            ),
+            (
+                "application",
+                &self.application_identifier,
+                CharacterSet::A,
+                128,
+            ),
+            (
+                "copyright file",
+                &self.copyright_file_identifier,
+                CharacterSet::File,
+                37,
+            ),
+            (
+                "abstract file",
+                &self.abstract_file_identifier,
+                CharacterSet::File,
+                37,
+            ),
+            (
+                "bibliographic file",
+                &self.bibliographic_file_identifier,
+                CharacterSet::File,
+                37,
+            ),
+        ];
+
+        for (field, value, character_set, len) in identifiers {
+            let Some(value) = value else {
+                continue;
+            };
+
+            if value.len() > len
+                || !value.chars().all(|c| character_set.contains(c))
+            {
+                return Err(IsoError::InvalidIdentifier {
+                    field,
+                    value: value.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Placement of the content of the files matching a glob pattern, like the
+/// mkisofs `-sort` weights
+#[derive(Debug, Clone, Default)]
+pub struct SortRule {
+    /// Path in the image, without leading '/': `?` matches any character and
+    /// `*` any sequence of characters except '/', `**` matches across
+    /// directories
+    pub pattern: String,
+    /// Files with higher weights are placed first, files without rule weigh 0
+    pub weight: i32,
+    /// Alignment of the content start, in logical blocks
+    pub alignment_in_lb: u32,
+}
+
+impl SortRule {
+    pub fn matches(&self, path: &str) -> bool {
+        crate::utils::glob_match(&self.pattern, path)
+    }
+}
+
+/// Handling of the host entries starting with a '.', hidden on Unix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DotFiles {
+    #[default]
+    Keep,
+    /// Record them with the ISO 9660 hidden flag
+    Hide,
+    /// Leave them out of the image
+    Skip,
+}
+
+/// Selection of the entries of the mirrored host directories
+#[derive(Debug, Clone, Default)]
+pub struct HostFilter {
+    /// Glob patterns, with the syntax of `SortRule::pattern` and relative to
+    /// the mirrored directory, of the files to keep, every file is kept when
+    /// empty. Directories left empty by these patterns are left out.
+    pub include: Vec<String>,
+    /// Glob patterns of the files and directories to leave out
+    pub exclude: Vec<String>,
+    /// Name of the ignore files, like `.gitignore`, whose patterns leave out
+    /// entries of their directory. The ignore files themselves are left out.
+    pub ignore_file: Option<String>,
+    pub dot_files: DotFiles,
+}
+
+#[derive(Default)]
 pub struct Opt {
     pub eltorito_opt: ElToritoOpt,
     pub embedded_boot: Option<String>,
     pub grub2_mbr: Option<String>,
+    /// Number of 512 bytes sectors loaded by the default El Torito entry, 0
+    /// for the whole boot image
     pub boot_load_size: u32,
     pub protective_msdos_label: bool,
+    /// Add a GPT describing the ISO 9660 data and the boot image of the EFI
+    /// El Torito entry, with its backup at the end of the image, so the image
+    /// can be written to a USB drive. Replaces `protective_msdos_label` by a
+    /// GPT protective MBR, only the first 512 bytes of the embedded boot file
+    /// are kept.
+    pub gpt: bool,
     pub primary_volume_name: Option<String>,
+    pub volume_opt: VolumeOpt,
+    /// Rules of the primary hierarchy identifiers, the original names are
+    /// kept in the Rock Ridge entries
+    pub interchange_level: InterchangeLevel,
+    /// Also describe the files in a Joliet hierarchy (UCS-2 names up to 64
+    /// characters)
+    pub joliet: bool,
+    /// Record every entry as owned by root with `rw-r--r--` or `rwxr-xr-x`
+    /// permissions instead of the host ones, executable files stay executable
+    pub normalize_attributes: bool,
+    /// Order and alignment of the file contents, the first matching rule of a
+    /// file applies, files of the same weight stay in tree order
+    pub sort_rules: Vec<SortRule>,
+    /// Let the files with identical contents share a single extent, the
+    /// first one placed
+    pub deduplicate: bool,
+    /// Glob patterns, with the syntax of `SortRule::pattern`, of the files
+    /// stored compressed with zisofs when it makes them smaller. Only the
+    /// readers of the Rock Ridge 'ZF' entries decompress them, Joliet sees
+    /// the compressed content.
+    pub compressed_files: Vec<String>,
+    /// Add a `SHA256SUMS` manifest of the file contents at the root, and the
+    /// digests of the whole image to the primary volume descriptor, see
+    /// `implant_checksums`. Only the outputs that can be read back support it.
+    pub checksums: bool,
+    /// Glob patterns of the files and directories recorded with the ISO 9660
+    /// hidden flag
+    pub hidden_files: Vec<String>,
+    /// Glob patterns of the files recorded with the ISO 9660 associated file
+    /// flag
+    pub associated_files: Vec<String>,
+    /// Selection of the entries of `input_files`
+    pub host_filter: HostFilter,
+    pub timestamp: Timestamp,
     pub input_files: Vec<PathBuf>,
 }
 
+#[derive(Default)]
 pub struct ElToritoOpt {
     pub eltorito_boot: Option<String>,
     pub no_emu_boot: bool,
     pub no_boot: bool,
     pub boot_info_table: bool,
     pub grub2_boot_info: bool,
+    /// Entries following the default one, consecutive entries of the same
+    /// platform share a section
+    pub entries: Vec<ElToritoEntry>,
+}
+
+impl ElToritoOpt {
+    /// Whether the image needs a boot catalog
+    pub fn has_boot_catalog(&self) -> bool {
+        self.eltorito_boot.is_some() || !self.entries.is_empty()
+    }
+}
+
+/// Platform of an El Torito boot entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Platform {
+    #[default]
+    X86,
+    PowerPc,
+    Mac,
+    /// UEFI, the boot image is a FAT file system
+    Efi,
+}
+
+impl Platform {
+    pub fn id(self) -> u8 {
+        match self {
+            Platform::X86 => 0x0,
+            Platform::PowerPc => 0x1,
+            Platform::Mac => 0x2,
+            Platform::Efi => 0xEF,
+        }
+    }
+}
+
+/// No emulation El Torito boot entry
+#[derive(Debug, Clone, Default)]
+pub struct ElToritoEntry {
+    pub platform: Platform,
+    /// Path of the boot image in the image
+    pub boot_file: String,
+    /// Number of 512 bytes sectors to load, 0 for the whole boot image
+    pub load_size: u16,
+    pub no_boot: bool,
 }
@@ -0,0 +1,1266 @@
+use crate::utils::{self, Hierarchy, LOGIC_SIZE, LOGIC_SIZE_U32};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use chrono::prelude::*;
+use flate2::read::ZlibDecoder;
+
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Maximum directory nesting followed while walking the hierarchy, protects
+/// against looping or maliciously deep images.
+const MAX_DIRECTORY_DEPTH: usize = 256;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_blocks<R>(reader: &mut R, lba: u32, len: usize) -> io::Result<Vec<u8>>
+where
+    R: Read + Seek,
+{
+    reader.seek(SeekFrom::Start(u64::from(lba) * u64::from(LOGIC_SIZE_U32)))?;
+    let mut buff = vec![0; len];
+    reader.read_exact(&mut buff)?;
+    Ok(buff)
+}
+
+/// Decode a UCS-2 big endian Joliet string.
+fn decode_ucs2(data: &[u8]) -> String {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+/// Decode a string field, trimming the padding spaces.
+fn read_str(data: &[u8], hierarchy: Hierarchy) -> String {
+    let value = match hierarchy {
+        Hierarchy::Primary => String::from_utf8_lossy(data).to_string(),
+        Hierarchy::Joliet => decode_ucs2(data),
+    };
+    value.trim_end_matches([' ', '\0']).to_string()
+}
+
+/// Decode a 7 bytes directory record date (ECMA-119 9.1.5).
+fn read_record_datetime(data: &[u8]) -> Option<DateTime<FixedOffset>> {
+    if data[..6].iter().all(|x| *x == 0) {
+        return None;
+    }
+
+    let offset = i32::from(i8::from_ne_bytes([data[6]])) * 15 * 60;
+    FixedOffset::east_opt(offset)?
+        .with_ymd_and_hms(
+            1900 + i32::from(data[0]),
+            u32::from(data[1]),
+            u32::from(data[2]),
+            u32::from(data[3]),
+            u32::from(data[4]),
+            u32::from(data[5]),
+        )
+        .single()
+}
+
+/// Decode a 17 bytes volume descriptor date (ECMA-119 8.4.26.1).
+fn read_volume_datetime(data: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let digits = std::str::from_utf8(&data[..16]).ok()?;
+    if digits.bytes().all(|x| x == b'0') {
+        return None;
+    }
+
+    let field = |range: std::ops::Range<usize>| -> Option<u32> {
+        digits.get(range)?.parse().ok()
+    };
+
+    let offset = i32::from(i8::from_ne_bytes([data[16]])) * 15 * 60;
+    let datetime = FixedOffset::east_opt(offset)?
+        .with_ymd_and_hms(
+            i32::try_from(field(0..4)?).ok()?,
+            field(4..6)?,
+            field(6..8)?,
+            field(8..10)?,
+            field(10..12)?,
+            field(12..14)?,
+        )
+        .single()?;
+
+    datetime.with_nanosecond(field(14..16)? * 10_000_000)
+}
+
+/// Rock Ridge 'PX' entry content (IEEE P1282 4.1.1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PosixAttributes {
+    pub mode: u32,
+    pub links: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub serial: Option<u32>,
+}
+
+/// Compression of a file content from a zisofs 'ZF' entry, the content
+/// starts with a header and a table of pointers to the compressed blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zisofs {
+    /// Size of the header, in units of 4 bytes
+    pub header_size: u8,
+    pub log2_block_size: u8,
+    pub uncompressed_size: u32,
+}
+
+/// Rock Ridge informations collected from a directory record system use area.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RockRidge {
+    pub name: Option<String>,
+    pub posix: Option<PosixAttributes>,
+    /// Modification time from the 'TF' entry
+    pub modified: Option<DateTime<FixedOffset>>,
+    /// Target of a symbolic link, from the 'SL' entries
+    pub symlink: Option<String>,
+    /// Location of the relocated directory of a placeholder ('CL' entry)
+    pub child_link: Option<u32>,
+    /// Location of the original parent of a relocated directory ('PL' entry)
+    pub parent_link: Option<u32>,
+    /// Directory moved to the relocation directory ('RE' entry)
+    pub relocated: bool,
+    /// Content compression ('ZF' entry)
+    pub zisofs: Option<Zisofs>,
+}
+
+/// Modification time of a Rock Ridge 'TF' entry (IEEE P1282 4.1.6)
+fn read_tf_modified(entry: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let flags = *entry.get(4)?;
+    // MODIFY
+    if flags & 0x2 == 0 {
+        return None;
+    }
+
+    // LONG_FORM uses volume descriptor dates
+    let len = if flags & 0x80 != 0 { 17 } else { 7 };
+    // Skip the creation time if present
+    let start = 5 + if flags & 0x1 != 0 { len } else { 0 };
+    let data = entry.get(start..start + len)?;
+
+    if len == 17 {
+        read_volume_datetime(data)
+    } else {
+        read_record_datetime(data)
+    }
+}
+
+/// Append the component records of a Rock Ridge 'SL' entry (IEEE P1282
+/// 4.1.3) to `target`, `continued` tells if the last component goes on in
+/// the next record
+fn read_sl_components(entry: &[u8], target: &mut String, continued: &mut bool) {
+    let mut offset = 5;
+
+    while offset + 2 <= entry.len() {
+        let flags = entry[offset];
+        let len = usize::from(entry[offset + 1]);
+        let Some(content) = entry.get(offset + 2..offset + 2 + len) else {
+            break;
+        };
+
+        if !*continued && !target.is_empty() && !target.ends_with('/') {
+            target.push('/');
+        }
+
+        if flags & 0x8 != 0 {
+            // ROOT
+            target.clear();
+            target.push('/');
+        } else if flags & 0x4 != 0 {
+            target.push_str("..");
+        } else if flags & 0x2 != 0 {
+            target.push('.');
+        } else {
+            target.push_str(&String::from_utf8_lossy(content));
+        }
+
+        *continued = flags & 0x1 != 0;
+        offset += 2 + len;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryRecord {
+    pub lba: u32,
+    pub data_length: u32,
+    pub recording_time: Option<DateTime<FixedOffset>>,
+    pub flags: u8,
+    pub identifier: Vec<u8>,
+    pub system_use: Vec<u8>,
+    pub rock_ridge: RockRidge,
+}
+
+impl DirectoryRecord {
+    fn parse(data: &[u8]) -> io::Result<DirectoryRecord> {
+        let Some(&record_len) = data.first() else {
+            return Err(invalid_data("missing directory record"));
+        };
+
+        // Also makes sure the fixed part of the record is there
+        let record_len = usize::from(record_len);
+        if record_len < 0x22 || record_len > data.len() {
+            return Err(invalid_data("invalid directory record length"));
+        }
+
+        let identifier_len = usize::from(data[32]);
+        let identifier_end = 33 + identifier_len;
+        if identifier_end > record_len {
+            return Err(invalid_data("invalid file identifier length"));
+        }
+
+        // padding if even
+        let system_use_start = identifier_end + (1 - identifier_len % 2);
+
+        Ok(DirectoryRecord {
+            lba: LittleEndian::read_u32(&data[2..]),
+            data_length: LittleEndian::read_u32(&data[10..]),
+            recording_time: read_record_datetime(&data[18..25]),
+            flags: data[25],
+            identifier: data[33..identifier_end].to_vec(),
+            system_use: data[system_use_start.min(record_len)..record_len]
+                .to_vec(),
+            rock_ridge: RockRidge::default(),
+        })
+    }
+
+    /// Modification time, the Rock Ridge one if available or the recording
+    /// time of the record
+    pub fn modified(&self) -> Option<DateTime<FixedOffset>> {
+        self.rock_ridge.modified.or(self.recording_time)
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & 0x2 != 0
+    }
+
+    /// True for the '.' and '..' records
+    pub fn is_special(&self) -> bool {
+        self.identifier == [0] || self.identifier == [1]
+    }
+
+    /// Name of the entry, the Rock Ridge one if available or the ISO 9660
+    /// identifier without its version.
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.rock_ridge.name {
+            return name.clone();
+        }
+
+        self.identifier_name(Hierarchy::Primary)
+    }
+
+    fn identifier_name(&self, hierarchy: Hierarchy) -> String {
+        let identifier = match hierarchy {
+            Hierarchy::Primary => String::from_utf8_lossy(&self.identifier),
+            Hierarchy::Joliet => decode_ucs2(&self.identifier).into(),
+        };
+        let identifier = identifier.split(';').next().unwrap_or_default();
+
+        if self.is_directory() {
+            identifier.to_string()
+        } else {
+            identifier.trim_end_matches('.').to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTableRecord {
+    pub identifier: Vec<u8>,
+    pub lba: u32,
+    pub parent_index: u16,
+}
+
+impl PathTableRecord {
+    /// Records of a whole path table, shared with the verifier
+    pub(crate) fn parse_table<Order: ByteOrder>(
+        data: &[u8],
+    ) -> io::Result<Vec<PathTableRecord>> {
+        let mut res = Vec::new();
+
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let identifier_len = usize::from(data[offset]);
+            let identifier_end = offset + 8 + identifier_len;
+            if identifier_len == 0 || identifier_end > data.len() {
+                return Err(invalid_data("invalid path table record"));
+            }
+
+            res.push(PathTableRecord {
+                identifier: data[offset + 8..identifier_end].to_vec(),
+                lba: Order::read_u32(&data[offset + 2..]),
+                parent_index: Order::read_u16(&data[offset + 6..]),
+            });
+
+            // padding if odd
+            offset = identifier_end + identifier_len % 2;
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrimaryVolumeDescriptor {
+    pub system_identifier: String,
+    pub volume_identifier: String,
+    pub volume_space_size: u32,
+    pub volume_set_size: u16,
+    pub volume_sequence_number: u16,
+    pub logical_block_size: u16,
+    pub path_table_size: u32,
+    pub path_table_l_lba: u32,
+    pub path_table_m_lba: u32,
+    pub root: DirectoryRecord,
+    pub volume_set_identifier: String,
+    pub publisher_identifier: String,
+    pub data_preparer_identifier: String,
+    pub application_identifier: String,
+    pub copyright_file_identifier: String,
+    pub abstract_file_identifier: String,
+    pub bibliographic_file_identifier: String,
+    pub creation_time: Option<DateTime<FixedOffset>>,
+    pub modification_time: Option<DateTime<FixedOffset>>,
+    pub expiration_time: Option<DateTime<FixedOffset>>,
+    pub effective_time: Option<DateTime<FixedOffset>>,
+    pub application_use: Vec<u8>,
+}
+
+impl PrimaryVolumeDescriptor {
+    fn parse(
+        data: &[u8],
+        hierarchy: Hierarchy,
+    ) -> io::Result<PrimaryVolumeDescriptor> {
+        let read_str = |data| read_str(data, hierarchy);
+
+        let logical_block_size = LittleEndian::read_u16(&data[128..]);
+        if usize::from(logical_block_size) != LOGIC_SIZE {
+            return Err(invalid_data(format!(
+                "unsupported logical block size {logical_block_size}"
+            )));
+        }
+
+        Ok(PrimaryVolumeDescriptor {
+            system_identifier: read_str(&data[8..40]),
+            volume_identifier: read_str(&data[40..72]),
+            volume_space_size: LittleEndian::read_u32(&data[80..]),
+            volume_set_size: LittleEndian::read_u16(&data[120..]),
+            volume_sequence_number: LittleEndian::read_u16(&data[124..]),
+            logical_block_size,
+            path_table_size: LittleEndian::read_u32(&data[132..]),
+            path_table_l_lba: LittleEndian::read_u32(&data[140..]),
+            path_table_m_lba: BigEndian::read_u32(&data[148..]),
+            root: DirectoryRecord::parse(&data[156..190])?,
+            volume_set_identifier: read_str(&data[190..318]),
+            publisher_identifier: read_str(&data[318..446]),
+            data_preparer_identifier: read_str(&data[446..574]),
+            application_identifier: read_str(&data[574..702]),
+            copyright_file_identifier: read_str(&data[702..739]),
+            abstract_file_identifier: read_str(&data[739..776]),
+            bibliographic_file_identifier: read_str(&data[776..813]),
+            creation_time: read_volume_datetime(&data[813..830]),
+            modification_time: read_volume_datetime(&data[830..847]),
+            expiration_time: read_volume_datetime(&data[847..864]),
+            effective_time: read_volume_datetime(&data[864..881]),
+            application_use: data[883..1395].to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    pub platform_id: u8,
+    pub bootable: bool,
+    pub media_type: u8,
+    pub load_segment: u16,
+    pub system_type: u8,
+    pub sector_count: u16,
+    pub load_rba: u32,
+}
+
+impl BootEntry {
+    fn parse(data: &[u8], platform_id: u8) -> BootEntry {
+        BootEntry {
+            platform_id,
+            bootable: data[0] == 0x88,
+            media_type: data[1],
+            load_segment: LittleEndian::read_u16(&data[2..]),
+            system_type: data[4],
+            sector_count: LittleEndian::read_u16(&data[6..]),
+            load_rba: LittleEndian::read_u32(&data[8..]),
+        }
+    }
+}
+
+/// El Torito boot catalog.
+#[derive(Debug, Clone)]
+pub struct BootCatalog {
+    pub lba: u32,
+    pub platform_id: u8,
+    pub id_string: Vec<u8>,
+    pub checksum: u16,
+    /// Default entry followed by the entries of every section.
+    pub entries: Vec<BootEntry>,
+}
+
+impl BootCatalog {
+    fn parse(data: &[u8], lba: u32) -> io::Result<BootCatalog> {
+        // Validation Entry
+        if data[0] != 0x1 || data[0x1E] != 0x55 || data[0x1F] != 0xAA {
+            return Err(invalid_data("invalid boot catalog validation entry"));
+        }
+
+        let platform_id = data[1];
+        let mut entries =
+            vec![BootEntry::parse(&data[0x20..0x40], platform_id)];
+
+        // Section headers and their entries
+        let mut offset = 0x40;
+        while offset + 0x20 <= data.len() {
+            let header = &data[offset..offset + 0x20];
+            if header[0] != 0x90 && header[0] != 0x91 {
+                break;
+            }
+
+            let section_platform_id = header[1];
+            let entry_count = usize::from(LittleEndian::read_u16(&header[2..]));
+            offset += 0x20;
+
+            for _ in 0..entry_count {
+                if offset + 0x20 > data.len() {
+                    return Err(invalid_data("truncated boot catalog"));
+                }
+                entries.push(BootEntry::parse(
+                    &data[offset..offset + 0x20],
+                    section_platform_id,
+                ));
+                offset += 0x20;
+            }
+
+            if header[0] == 0x91 {
+                break;
+            }
+        }
+
+        Ok(BootCatalog {
+            lba,
+            platform_id,
+            id_string: data[0x4..0x1C].to_vec(),
+            checksum: LittleEndian::read_u16(&data[0x1C..]),
+            entries,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IsoFile {
+    pub name: String,
+    /// First directory record of the file
+    pub record: DirectoryRecord,
+    /// Location and size of every extent, in order
+    pub extents: Vec<(u32, u32)>,
+}
+
+impl IsoFile {
+    /// Size of the content, once decompressed for zisofs files
+    pub fn size(&self) -> u64 {
+        match self.record.rock_ridge.zisofs {
+            Some(zisofs) => u64::from(zisofs.uncompressed_size),
+            None => self.stored_size(),
+        }
+    }
+
+    /// Size of the content as recorded in the image
+    pub fn stored_size(&self) -> u64 {
+        self.extents.iter().map(|&(_, size)| u64::from(size)).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IsoDirectory {
+    pub name: String,
+    pub record: DirectoryRecord,
+    pub directories: Vec<IsoDirectory>,
+    pub files: Vec<IsoFile>,
+}
+
+impl IsoDirectory {
+    pub fn get_directory(&self, path: &str) -> Option<&IsoDirectory> {
+        path.split('/')
+            .filter(|x| !x.is_empty())
+            .try_fold(self, |dir, name| {
+                dir.directories.iter().find(|x| x.name == name)
+            })
+    }
+
+    pub fn get_file(&self, path: &str) -> Option<&IsoFile> {
+        let (dir_path, file_name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        self.get_directory(dir_path)?
+            .files
+            .iter()
+            .find(|x| x.name == file_name)
+    }
+}
+
+/// Name of an entry as a single host path component, refusing the ones that
+/// would be written outside of the extraction directory
+fn host_name(name: &str) -> io::Result<&str> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\', '\0'])
+    {
+        return Err(invalid_data(format!("invalid entry name {name:?}")));
+    }
+
+    Ok(name)
+}
+
+/// Refuse to write over an existing entry, symbolic links included
+fn check_not_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _path: &Path) -> io::Result<()> {
+    // Creating symbolic links needs special privileges, skip them
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_directory_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    File::open(path)?.set_modified(time)
+}
+
+#[cfg(not(unix))]
+fn set_directory_modified(_path: &Path, _time: SystemTime) -> io::Result<()> {
+    // Directories can't be opened as files
+    Ok(())
+}
+
+/// Read + Seek handle over the content of a file of an [IsoImage], zisofs
+/// files are decompressed
+pub struct FileReader<'a, R> {
+    reader: &'a mut R,
+    /// Byte offset and size of every extent
+    extents: Vec<(u64, u64)>,
+    size: u64,
+    pos: u64,
+    zisofs: Option<Zisofs>,
+    /// Last decompressed block and its index
+    block: Option<(u64, Vec<u8>)>,
+}
+
+impl<R> FileReader<'_, R>
+where
+    R: Read + Seek,
+{
+    /// Read the content as recorded in the image, at `pos`
+    fn read_stored(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        // Find the extent holding the position
+        let mut extent_pos = pos;
+        let Some(&(start, size)) = self.extents.iter().find(|&&(_, size)| {
+            if extent_pos < size {
+                return true;
+            }
+            extent_pos -= size;
+            false
+        }) else {
+            return Ok(0);
+        };
+
+        let remaining = size - extent_pos;
+        let len = usize::try_from(remaining)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.reader.seek(SeekFrom::Start(start + extent_pos))?;
+        self.reader.read(&mut buf[..len])
+    }
+
+    fn read_stored_exact(
+        &mut self,
+        mut pos: u64,
+        mut buf: &mut [u8],
+    ) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_stored(pos, buf)? {
+                0 => return Err(invalid_data("truncated zisofs file")),
+                read => {
+                    pos += u64::try_from(read).unwrap();
+                    buf = &mut buf[read..];
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decompress the zisofs block `index`, found with the block pointers
+    /// following the header
+    fn read_zisofs_block(
+        &mut self,
+        zisofs: Zisofs,
+        index: u64,
+    ) -> io::Result<Vec<u8>> {
+        let block_size = 1 << zisofs.log2_block_size;
+        let block_len =
+            usize::try_from((self.size - index * block_size).min(block_size))
+                .unwrap();
+
+        let mut pointers = [0; 8];
+        self.read_stored_exact(
+            4 * u64::from(zisofs.header_size) + 4 * index,
+            &mut pointers,
+        )?;
+        let start = LittleEndian::read_u32(&pointers);
+        let end = LittleEndian::read_u32(&pointers[4..]);
+        if end < start {
+            return Err(invalid_data("invalid zisofs block pointers"));
+        }
+
+        // Blocks of zeros aren't stored
+        if start == end {
+            return Ok(vec![0; block_len]);
+        }
+
+        let mut compressed = vec![0; usize::try_from(end - start).unwrap()];
+        self.read_stored_exact(u64::from(start), &mut compressed)?;
+
+        let mut res = Vec::with_capacity(block_len);
+        ZlibDecoder::new(&compressed[..])
+            .take(u64::try_from(block_len).unwrap())
+            .read_to_end(&mut res)?;
+        if res.len() != block_len {
+            return Err(invalid_data("truncated zisofs block"));
+        }
+        Ok(res)
+    }
+}
+
+impl<R> Read for FileReader<'_, R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(zisofs) = self.zisofs else {
+            let read = self.read_stored(self.pos, buf)?;
+            self.pos += u64::try_from(read).unwrap();
+            return Ok(read);
+        };
+
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        // Linux only supports blocks from 32 KiB to 128 KiB
+        if !(15..=17).contains(&zisofs.log2_block_size) {
+            return Err(invalid_data("unsupported zisofs block size"));
+        }
+
+        let index = self.pos >> zisofs.log2_block_size;
+        if self.block.as_ref().is_none_or(|(x, _)| *x != index) {
+            let block = self.read_zisofs_block(zisofs, index)?;
+            self.block = Some((index, block));
+        }
+
+        let block = &self.block.as_ref().unwrap().1;
+        let block_pos =
+            usize::try_from(self.pos - (index << zisofs.log2_block_size))
+                .unwrap();
+        let len = buf.len().min(block.len() - block_pos);
+        buf[..len].copy_from_slice(&block[block_pos..block_pos + len]);
+        self.pos += u64::try_from(len).unwrap();
+        Ok(len)
+    }
+}
+
+impl<R> Seek for FileReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.size.checked_add_signed(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+        };
+
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+/// ISO 9660 image opened for reading.
+pub struct IsoImage<R> {
+    reader: R,
+    pub primary: PrimaryVolumeDescriptor,
+    pub path_table_l: Vec<PathTableRecord>,
+    pub path_table_m: Vec<PathTableRecord>,
+    pub root: IsoDirectory,
+    /// Joliet supplementary volume descriptor, its identifiers are decoded
+    pub joliet: Option<PrimaryVolumeDescriptor>,
+    pub joliet_root: Option<IsoDirectory>,
+    pub boot_catalog: Option<BootCatalog>,
+    /// Number of bytes to skip at the start of every system use area, `None`
+    /// if the image doesn't use SUSP.
+    pub susp_skip: Option<usize>,
+}
+
+impl<R> IsoImage<R>
+where
+    R: Read + Seek,
+{
+    pub fn open(mut reader: R) -> io::Result<IsoImage<R>> {
+        let mut primary = None;
+        let mut joliet = None;
+        let mut boot_catalog_lba = None;
+
+        let mut lba = 0x10;
+        loop {
+            let data = read_blocks(&mut reader, lba, LOGIC_SIZE)?;
+            if &data[1..6] != b"CD001" {
+                return Err(invalid_data(format!(
+                    "invalid volume descriptor at LBA {lba}"
+                )));
+            }
+
+            match data[0] {
+                0 if data[7..30] == *b"EL TORITO SPECIFICATION" => {
+                    boot_catalog_lba =
+                        Some(LittleEndian::read_u32(&data[0x47..]));
+                }
+                1 if primary.is_none() => {
+                    primary = Some(PrimaryVolumeDescriptor::parse(
+                        &data,
+                        Hierarchy::Primary,
+                    )?);
+                }
+                // Joliet escape sequences (UCS-2 Level 1, 2 or 3)
+                2 if joliet.is_none()
+                    && data[88..90] == *b"%/"
+                    && matches!(data[90], b'@' | b'C' | b'E') =>
+                {
+                    joliet = Some(PrimaryVolumeDescriptor::parse(
+                        &data,
+                        Hierarchy::Joliet,
+                    )?);
+                }
+                0xff => break,
+                _ => {}
+            }
+
+            lba += 1;
+        }
+
+        let primary =
+            primary.ok_or_else(|| invalid_data("missing primary volume"))?;
+
+        let path_table_size = usize::try_from(primary.path_table_size).unwrap();
+        let path_table_l = Self::read_path_table::<LittleEndian>(
+            &mut reader,
+            primary.path_table_l_lba,
+            path_table_size,
+        )?;
+        let path_table_m = Self::read_path_table::<BigEndian>(
+            &mut reader,
+            primary.path_table_m_lba,
+            path_table_size,
+        )?;
+
+        let boot_catalog = match boot_catalog_lba {
+            Some(lba) => Some(BootCatalog::parse(
+                &read_blocks(&mut reader, lba, LOGIC_SIZE)?,
+                lba,
+            )?),
+            None => None,
+        };
+
+        // Filled once the SUSP usage is known
+        let root = IsoDirectory {
+            name: String::new(),
+            record: primary.root.clone(),
+            directories: Vec::new(),
+            files: Vec::new(),
+        };
+
+        let mut image = IsoImage {
+            reader,
+            primary,
+            path_table_l,
+            path_table_m,
+            root,
+            joliet,
+            joliet_root: None,
+            boot_catalog,
+            susp_skip: None,
+        };
+
+        image.root = image.read_root()?;
+
+        if let Some(joliet) = &image.joliet {
+            let mut visited = HashSet::new();
+            image.joliet_root = Some(image.read_directory(
+                String::new(),
+                joliet.root.clone(),
+                Hierarchy::Joliet,
+                &mut visited,
+                0,
+            )?);
+        }
+
+        Ok(image)
+    }
+
+    fn read_path_table<Order: ByteOrder>(
+        reader: &mut R,
+        lba: u32,
+        size: usize,
+    ) -> io::Result<Vec<PathTableRecord>> {
+        let data = read_blocks(reader, lba, size)?;
+        PathTableRecord::parse_table::<Order>(&data)
+    }
+
+    fn read_root(&mut self) -> io::Result<IsoDirectory> {
+        let root_record = self.primary.root.clone();
+        let data = self.read_extent(&root_record)?;
+        let dot = DirectoryRecord::parse(&data)?;
+
+        // SUSP 'SP' entry (IEEE P1281 5.3)
+        let sp = &dot.system_use;
+        if sp.len() >= 7 && &sp[0..2] == b"SP" && sp[4..6] == [0xBE, 0xEF] {
+            self.susp_skip = Some(usize::from(sp[6]));
+        }
+
+        let mut visited = HashSet::new();
+        self.read_directory(
+            String::new(),
+            root_record,
+            Hierarchy::Primary,
+            &mut visited,
+            0,
+        )
+    }
+
+    fn read_extent(&mut self, record: &DirectoryRecord) -> io::Result<Vec<u8>> {
+        read_blocks(
+            &mut self.reader,
+            record.lba,
+            usize::try_from(record.data_length).unwrap(),
+        )
+    }
+
+    /// '.' record of the directory at `lba`
+    fn read_dot_record(&mut self, lba: u32) -> io::Result<DirectoryRecord> {
+        let data = read_blocks(&mut self.reader, lba, LOGIC_SIZE)?;
+        let mut res = DirectoryRecord::parse(&data)?;
+        res.lba = lba;
+        Ok(res)
+    }
+
+    fn read_directory(
+        &mut self,
+        name: String,
+        record: DirectoryRecord,
+        hierarchy: Hierarchy,
+        visited: &mut HashSet<u32>,
+        depth: usize,
+    ) -> io::Result<IsoDirectory> {
+        if depth > MAX_DIRECTORY_DEPTH || !visited.insert(record.lba) {
+            return Err(invalid_data(format!(
+                "directory loop detected at LBA {}",
+                record.lba
+            )));
+        }
+
+        let data = self.read_extent(&record)?;
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut pending_file: Option<IsoFile> = None;
+
+        let mut offset = 0;
+        while offset < data.len() {
+            // Records never cross a logical block, a zero length means the
+            // rest of the block is padding
+            if data[offset] == 0 {
+                offset = (offset + 1).next_multiple_of(LOGIC_SIZE);
+                continue;
+            }
+
+            let mut child = DirectoryRecord::parse(&data[offset..])?;
+            offset += usize::from(data[offset]);
+
+            if child.is_special() {
+                continue;
+            }
+
+            let child_name = match (hierarchy, self.susp_skip) {
+                (Hierarchy::Primary, Some(skip)) => {
+                    child.rock_ridge = self.read_rock_ridge(&child, skip)?;
+                    child.name()
+                }
+                _ => child.identifier_name(hierarchy),
+            };
+
+            // Relocated directories are shown at their original place
+            if child.rock_ridge.relocated {
+                continue;
+            }
+
+            if let Some(lba) = child.rock_ridge.child_link {
+                let mut relocated = self.read_dot_record(lba)?;
+                relocated.rock_ridge = child.rock_ridge;
+                directories.push(self.read_directory(
+                    child_name,
+                    relocated,
+                    hierarchy,
+                    visited,
+                    depth + 1,
+                )?);
+            } else if child.is_directory() {
+                directories.push(self.read_directory(
+                    child_name,
+                    child,
+                    hierarchy,
+                    visited,
+                    depth + 1,
+                )?);
+            } else {
+                let extent = (child.lba, child.data_length);
+                let is_final = child.flags & 0x80 == 0;
+
+                // Records of the following extents of a multi-extent file
+                // only add their extent to the first one
+                let mut file = pending_file.take().unwrap_or(IsoFile {
+                    name: child_name,
+                    record: child,
+                    extents: Vec::new(),
+                });
+                file.extents.push(extent);
+
+                if is_final {
+                    files.push(file);
+                } else {
+                    pending_file = Some(file);
+                }
+            }
+        }
+
+        if pending_file.is_some() {
+            return Err(invalid_data("multi-extent file without final record"));
+        }
+
+        Ok(IsoDirectory {
+            name,
+            record,
+            directories,
+            files,
+        })
+    }
+
+    fn read_rock_ridge(
+        &mut self,
+        record: &DirectoryRecord,
+        skip: usize,
+    ) -> io::Result<RockRidge> {
+        let mut res = RockRidge::default();
+        let mut name: Option<String> = None;
+        let mut symlink: Option<String> = None;
+        let mut symlink_continued = false;
+
+        let mut area =
+            record.system_use.get(skip..).unwrap_or_default().to_vec();
+        let mut continuation_count = 0;
+
+        loop {
+            let mut continuation = None;
+            let mut offset = 0;
+
+            while offset + 4 <= area.len() {
+                let entry_len = usize::from(area[offset + 2]);
+                if entry_len < 4 || offset + entry_len > area.len() {
+                    break;
+                }
+
+                let entry = &area[offset..offset + entry_len];
+                match &entry[0..2] {
+                    // SUSP 'CE' entry (IEEE P1281 5.1)
+                    b"CE" if entry_len >= 28 => {
+                        continuation = Some((
+                            LittleEndian::read_u32(&entry[4..]),
+                            LittleEndian::read_u32(&entry[12..]),
+                            LittleEndian::read_u32(&entry[20..]),
+                        ));
+                    }
+                    // SUSP 'ST' entry (IEEE P1281 5.4)
+                    b"ST" => break,
+                    // RRIP 'PX' entry (IEEE P1282 4.1.1)
+                    b"PX" if entry_len >= 36 => {
+                        res.posix = Some(PosixAttributes {
+                            mode: LittleEndian::read_u32(&entry[4..]),
+                            links: LittleEndian::read_u32(&entry[12..]),
+                            uid: LittleEndian::read_u32(&entry[20..]),
+                            gid: LittleEndian::read_u32(&entry[28..]),
+                            serial: (entry_len >= 44)
+                                .then(|| LittleEndian::read_u32(&entry[36..])),
+                        });
+                    }
+                    // RRIP 'NM' entry (IEEE P1282 4.1.4)
+                    b"NM" if entry_len >= 5 => {
+                        let flags = entry[4];
+                        // Only keep real names, not '.' or '..' aliases
+                        if flags & 0x6 == 0 {
+                            name.get_or_insert_default().push_str(
+                                &String::from_utf8_lossy(&entry[5..]),
+                            );
+                        }
+                    }
+                    // RRIP 'SL' entry (IEEE P1282 4.1.3)
+                    b"SL" if entry_len >= 5 => {
+                        read_sl_components(
+                            entry,
+                            symlink.get_or_insert_default(),
+                            &mut symlink_continued,
+                        );
+                    }
+                    // RRIP 'CL' entry (IEEE P1282 4.1.5.1)
+                    b"CL" if entry_len >= 12 => {
+                        res.child_link =
+                            Some(LittleEndian::read_u32(&entry[4..]));
+                    }
+                    // RRIP 'PL' entry (IEEE P1282 4.1.5.2)
+                    b"PL" if entry_len >= 12 => {
+                        res.parent_link =
+                            Some(LittleEndian::read_u32(&entry[4..]));
+                    }
+                    // RRIP 'RE' entry (IEEE P1282 4.1.5.3)
+                    b"RE" => res.relocated = true,
+                    // RRIP 'TF' entry (IEEE P1282 4.1.6)
+                    b"TF" if entry_len >= 5 => {
+                        res.modified = read_tf_modified(entry);
+                    }
+                    // zisofs 'ZF' entry, only "paged zlib" exists
+                    b"ZF" if entry_len >= 16 && &entry[4..6] == b"pz" => {
+                        res.zisofs = Some(Zisofs {
+                            header_size: entry[6],
+                            log2_block_size: entry[7],
+                            uncompressed_size: LittleEndian::read_u32(
+                                &entry[8..],
+                            ),
+                        });
+                    }
+                    _ => {}
+                }
+
+                offset += entry_len;
+            }
+
+            match continuation {
+                Some((lba, ce_offset, len)) if continuation_count < 16 => {
+                    continuation_count += 1;
+
+                    let ce_offset = usize::try_from(ce_offset).unwrap();
+                    let len = usize::try_from(len).unwrap();
+                    let data =
+                        read_blocks(&mut self.reader, lba, ce_offset + len)?;
+                    area = data[ce_offset..].to_vec();
+                }
+                _ => break,
+            }
+        }
+
+        res.name = name;
+        res.symlink = symlink;
+        Ok(res)
+    }
+
+    /// Open a file content as a Read + Seek handle.
+    pub fn open_file(&mut self, file: &IsoFile) -> FileReader<'_, R> {
+        FileReader {
+            reader: &mut self.reader,
+            extents: file
+                .extents
+                .iter()
+                .map(|&(lba, size)| {
+                    (
+                        u64::from(lba) * u64::from(LOGIC_SIZE_U32),
+                        u64::from(size),
+                    )
+                })
+                .collect(),
+            size: file.size(),
+            pos: 0,
+            zisofs: file.record.rock_ridge.zisofs,
+            block: None,
+        }
+    }
+
+    /// Read the whole content of the file at the given path.
+    pub fn read_file(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        let file = self.root.get_file(path).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{path} not found"))
+        })?;
+
+        let mut res = Vec::new();
+        self.open_file(&file).read_to_end(&mut res)?;
+        Ok(res)
+    }
+
+    /// Write the files and directories of the image under `path`, named
+    /// after their Rock Ridge names or their identifiers, with their
+    /// modification times. Existing entries are never overwritten, and the
+    /// symbolic links are created once everything else is written, so no
+    /// entry of the image can be written through them.
+    pub fn extract(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+
+        let root = self.root.clone();
+        let mut symlinks = Vec::new();
+        let mut directories = Vec::new();
+        self.extract_directory(&root, path, &mut symlinks, &mut directories)?;
+
+        for (target, link_path) in symlinks {
+            create_symlink(&target, &link_path)?;
+        }
+
+        // Last, creating the entries updates the modification time
+        for (directory_path, modified) in directories {
+            set_directory_modified(&directory_path, modified)?;
+        }
+
+        Ok(())
+    }
+
+    fn extract_directory(
+        &mut self,
+        directory: &IsoDirectory,
+        path: &Path,
+        symlinks: &mut Vec<(String, PathBuf)>,
+        directories: &mut Vec<(PathBuf, SystemTime)>,
+    ) -> io::Result<()> {
+        for file in &directory.files {
+            let file_path = path.join(host_name(&file.name)?);
+            check_not_existing(&file_path)?;
+
+            if let Some(target) = &file.record.rock_ridge.symlink {
+                symlinks.push((target.clone(), file_path));
+                continue;
+            }
+
+            let mut host_file = File::options()
+                .write(true)
+                .create_new(true)
+                .open(&file_path)?;
+            io::copy(&mut self.open_file(file), &mut host_file)?;
+            if let Some(modified) = file.record.modified() {
+                host_file.set_modified(modified.into())?;
+            }
+        }
+
+        for child in &directory.directories {
+            let child_path = path.join(host_name(&child.name)?);
+            check_not_existing(&child_path)?;
+            fs::create_dir(&child_path)?;
+            self.extract_directory(child, &child_path, symlinks, directories)?;
+        }
+
+        if let Some(modified) = directory.record.modified() {
+            directories.push((path.to_path_buf(), modified.into()));
+        }
+
+        Ok(())
+    }
+
+    /// Check the El Torito boot catalog, as well as the boot info table of
+    /// the boot images having one
+    pub fn verify_boot_catalog(&mut self) -> io::Result<()> {
+        let Some(catalog) = self.boot_catalog.clone() else {
+            return Ok(());
+        };
+
+        let data = read_blocks(&mut self.reader, catalog.lba, LOGIC_SIZE)?;
+        if utils::eltorito_checksum(&data[..0x20]) != 0 {
+            return Err(invalid_data(
+                "invalid boot catalog validation entry checksum",
+            ));
+        }
+
+        // Default entry boot indicator
+        if data[0x20] != 0x0 && data[0x20] != 0x88 {
+            return Err(invalid_data("invalid boot catalog boot indicator"));
+        }
+
+        for entry in &catalog.entries {
+            if entry.media_type > 4 {
+                return Err(invalid_data("invalid boot media type"));
+            }
+
+            if entry.load_rba >= self.primary.volume_space_size {
+                return Err(invalid_data("boot image outside of the volume"));
+            }
+
+            // Boot info table, recognized by the primary volume descriptor
+            // and boot image locations it starts with
+            let header = read_blocks(&mut self.reader, entry.load_rba, 0x40)?;
+            if LittleEndian::read_u32(&header[0x8..]) != 0x10
+                || LittleEndian::read_u32(&header[0xC..]) != entry.load_rba
+            {
+                continue;
+            }
+
+            let length = LittleEndian::read_u32(&header[0x10..]);
+            let end_lba = u64::from(entry.load_rba)
+                + u64::from(length).div_ceil(u64::from(LOGIC_SIZE_U32));
+            if end_lba > u64::from(self.primary.volume_space_size) {
+                return Err(invalid_data(
+                    "boot info table length outside of the volume",
+                ));
+            }
+
+            let image = read_blocks(
+                &mut self.reader,
+                entry.load_rba,
+                usize::try_from(length).unwrap(),
+            )?;
+            let checksum = LittleEndian::read_u32(&header[0x14..]);
+            if utils::boot_info_table_checksum(&image) != checksum {
+                return Err(invalid_data("invalid boot info table checksum"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -0,0 +1,477 @@
+use crate::utils::{self, LOGIC_SIZE};
+use crate::zisofs;
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use chrono::prelude::*;
+
+use std::fs::Metadata;
+use std::io::Write;
+
+/// File type bits of a POSIX file mode
+pub const S_IFMT: u32 = 0o170_000;
+pub const S_IFDIR: u32 = 0o040_000;
+pub const S_IFREG: u32 = 0o100_000;
+pub const S_IFLNK: u32 = 0o120_000;
+
+/// Owner and permissions recorded in the Rock Ridge 'PX' entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PosixAttributes {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PosixAttributes {
+    /// Attributes of the entries without any host counterpart
+    pub fn new(file_type: u32) -> PosixAttributes {
+        let permissions = match file_type {
+            S_IFDIR => 0o755, // drwxr-xr-x
+            S_IFLNK => 0o777, // lrwxrwxrwx
+            _ => 0o644,       // rw-r--r--
+        };
+
+        PosixAttributes {
+            mode: file_type | permissions,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    /// Attributes of a host file, directory or symbolic link
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> PosixAttributes {
+        use std::os::unix::fs::MetadataExt;
+
+        PosixAttributes {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+
+    /// Attributes of a host file, directory or symbolic link
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> PosixAttributes {
+        let file_type = if metadata.is_symlink() {
+            S_IFLNK
+        } else if metadata.is_dir() {
+            S_IFDIR
+        } else {
+            S_IFREG
+        };
+
+        let mut res = PosixAttributes::new(file_type);
+        if metadata.permissions().readonly() {
+            res.mode &= !0o222;
+        }
+        res
+    }
+
+    /// Owned by root with the default permissions, executables stay
+    /// executable
+    pub fn normalize(&mut self) {
+        let file_type = self.mode & S_IFMT;
+        let executable = file_type == S_IFREG && self.mode & 0o111 != 0;
+
+        *self = PosixAttributes::new(file_type);
+        if executable {
+            self.mode |= 0o111;
+        }
+    }
+}
+
+/// SUSP 'SP' entry (IEEE P1281 5.3)
+pub fn write_sp(buff: &mut Vec<u8>) -> std::io::Result<()> {
+    buff.write_all(b"SP")?;
+    buff.write_u8(0x7)?;
+    buff.write_u8(0x1)?;
+    buff.write_u8(0xBE)?;
+    buff.write_u8(0xEF)?;
+    buff.write_u8(0x0)?;
+
+    Ok(())
+}
+
+/// Size of a SUSP 'CE' entry
+pub const CE_SIZE: usize = 0x1C;
+
+/// Split SUSP entries between a record and its continuation area, keeping
+/// the first entries that fit in `available` bytes along with a 'CE' entry
+pub fn split_system_use(
+    system_use: &[u8],
+    available: usize,
+) -> (Vec<u8>, Vec<u8>) {
+    // Leave room for the padding byte keeping the record length even
+    let available = available - available % 2;
+
+    if system_use.len() <= available {
+        return (system_use.to_vec(), Vec::new());
+    }
+
+    let mut offset = 0;
+    while offset < system_use.len() {
+        let entry_len = usize::from(system_use[offset + 2]);
+        if offset + entry_len + CE_SIZE > available {
+            break;
+        }
+
+        offset += entry_len;
+    }
+
+    (system_use[..offset].to_vec(), system_use[offset..].to_vec())
+}
+
+/// Size of the system use field of a record holding `system_use`, with a 'CE'
+/// entry if `continuation` isn't empty, padded to keep the record length even
+/// (ECMA-119 9.1.13)
+pub fn get_system_use_size(system_use: &[u8], continuation: &[u8]) -> usize {
+    let ce_size = if continuation.is_empty() { 0 } else { CE_SIZE };
+    (system_use.len() + ce_size).next_multiple_of(2)
+}
+
+/// Continuation areas of the records of a directory, packed in the logical
+/// blocks starting at `lba`
+#[derive(Debug, Default)]
+pub struct ContinuationAreas {
+    lba: u32,
+    data: Vec<u8>,
+}
+
+impl ContinuationAreas {
+    pub fn new(lba: u32) -> ContinuationAreas {
+        ContinuationAreas {
+            lba,
+            data: Vec::new(),
+        }
+    }
+
+    /// Store `entries` in continuation areas, chained by 'CE' entries when
+    /// they don't fit in a single logical block, and give the 'CE' entry
+    /// pointing to the first area
+    pub fn push(&mut self, entries: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut areas = Vec::new();
+        let mut rest = entries.to_vec();
+        while !rest.is_empty() {
+            let (area, next) = split_system_use(&rest, LOGIC_SIZE);
+            areas.push(area);
+            rest = next;
+        }
+
+        // An area can't cross a logical block
+        let mut positions = Vec::new();
+        let mut end = self.data.len();
+        for (index, area) in areas.iter().enumerate() {
+            let mut size = area.len();
+            if index + 1 < areas.len() {
+                size += CE_SIZE;
+            }
+
+            if end % LOGIC_SIZE + size > LOGIC_SIZE {
+                end = end.next_multiple_of(LOGIC_SIZE);
+            }
+
+            positions.push((end, size));
+            end += size;
+        }
+
+        for (index, area) in areas.iter().enumerate() {
+            self.data.resize(positions[index].0, 0);
+            self.data.extend_from_slice(area);
+
+            if let Some(&next) = positions.get(index + 1) {
+                let ce = self.get_ce(next)?;
+                self.data.extend(ce);
+            }
+        }
+
+        self.get_ce(positions[0])
+    }
+
+    /// 'CE' entry pointing to the area at `position` of `size` bytes
+    fn get_ce(
+        &self,
+        (position, size): (usize, usize),
+    ) -> std::io::Result<Vec<u8>> {
+        let mut res = Vec::new();
+        write_ce(
+            &mut res,
+            self.lba + u32::try_from(position / LOGIC_SIZE).unwrap(),
+            u32::try_from(position % LOGIC_SIZE).unwrap(),
+            u32::try_from(size).unwrap(),
+        )?;
+        Ok(res)
+    }
+
+    pub fn size_in_lb(&self) -> u32 {
+        u32::try_from(self.data.len().div_ceil(LOGIC_SIZE)).unwrap()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// SUSP 'CE' entry (IEEE P1281 5.1)
+pub fn write_ce(
+    buff: &mut Vec<u8>,
+    lba: u32,
+    offset: u32,
+    len: u32,
+) -> std::io::Result<()> {
+    buff.write_all(b"CE")?;
+    buff.write_u8(0x1c)?;
+    buff.write_u8(0x1)?;
+
+    // Location, offset and size of the continuation area
+    write_bothendian! {
+        buff.write_u32(lba)?;
+    }
+    write_bothendian! {
+        buff.write_u32(offset)?;
+    }
+    write_bothendian! {
+        buff.write_u32(len)?;
+    }
+
+    Ok(())
+}
+
+/// SUSP 'ER' entry (IEEE P1281 5.5) of Rock Ridge 1.2
+pub fn write_er(buff: &mut Vec<u8>) -> std::io::Result<()> {
+    let identifier = b"IEEE_1282";
+    let descriptor = b"THE IEEE 1282 PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS.";
+    let source = b"PLEASE CONTACT THE IEEE STANDARDS DEPARTMENT, PISCATAWAY, NJ, USA FOR THE 1282 SPECIFICATION.";
+
+    buff.write_all(b"ER")?;
+    buff.write_u8(
+        (0x8 + identifier.len() + descriptor.len() + source.len())
+            .try_into()
+            .unwrap(),
+    )?;
+    buff.write_u8(0x1)?;
+    buff.write_u8(identifier.len().try_into().unwrap())?;
+    buff.write_u8(descriptor.len().try_into().unwrap())?;
+    buff.write_u8(source.len().try_into().unwrap())?;
+    buff.write_u8(0x1)?; // Extension version
+    buff.write_all(identifier)?;
+    buff.write_all(descriptor)?;
+    buff.write_all(source)?;
+
+    Ok(())
+}
+
+/// RRIP 'PX' entry (IEEE P1282 4.1.1)
+pub fn write_px(
+    buff: &mut Vec<u8>,
+    attributes: &PosixAttributes,
+    links: u32,
+    serial: u32,
+) -> std::io::Result<()> {
+    buff.write_all(b"PX")?;
+    buff.write_u8(0x2c)?;
+    buff.write_u8(0x1)?;
+
+    // file mode
+    write_bothendian! {
+        buff.write_u32(attributes.mode)?;
+    }
+
+    // links
+    write_bothendian! {
+        buff.write_u32(links)?;
+    }
+
+    // user id
+    write_bothendian! {
+        buff.write_u32(attributes.uid)?;
+    }
+
+    // group id
+    write_bothendian! {
+        buff.write_u32(attributes.gid)?;
+    }
+
+    // "File Serial number"
+    write_bothendian! {
+        buff.write_u32(serial)?;
+    }
+
+    Ok(())
+}
+
+/// RRIP 'CL' entry (IEEE P1282 4.1.5.1) of the placeholder of a relocated
+/// directory, recorded at `lba`
+pub fn write_cl(buff: &mut Vec<u8>, lba: u32) -> std::io::Result<()> {
+    buff.write_all(b"CL")?;
+    buff.write_u8(0xC)?;
+    buff.write_u8(0x1)?;
+
+    write_bothendian! {
+        buff.write_u32(lba)?;
+    }
+
+    Ok(())
+}
+
+/// RRIP 'PL' entry (IEEE P1282 4.1.5.2) of the '..' record of a relocated
+/// directory, whose original parent is recorded at `lba`
+pub fn write_pl(buff: &mut Vec<u8>, lba: u32) -> std::io::Result<()> {
+    buff.write_all(b"PL")?;
+    buff.write_u8(0xC)?;
+    buff.write_u8(0x1)?;
+
+    write_bothendian! {
+        buff.write_u32(lba)?;
+    }
+
+    Ok(())
+}
+
+/// RRIP 'RE' entry (IEEE P1282 4.1.5.3) of a relocated directory
+pub fn write_re(buff: &mut Vec<u8>) -> std::io::Result<()> {
+    buff.write_all(b"RE")?;
+    buff.write_u8(0x4)?;
+    buff.write_u8(0x1)?;
+
+    Ok(())
+}
+
+/// RRIP 'TF' entry (IEEE P1282 4.1.6), with the same modification, access
+/// and attributes change time
+pub fn write_tf(
+    buff: &mut Vec<u8>,
+    timestamp: &DateTime<Utc>,
+) -> std::io::Result<()> {
+    buff.write_all(b"TF")?;
+    buff.write_u8(0x5 + 3 * 7)?;
+    buff.write_u8(0x1)?;
+
+    // MODIFY | ACCESS | ATTRIBUTES, short form
+    buff.write_u8(0x2 | 0x4 | 0x8)?;
+    for _ in 0..3 {
+        utils::write_record_datetime(buff, timestamp)?;
+    }
+
+    Ok(())
+}
+
+/// Maximum size of the name in a 'NM' entry
+const NM_MAX_LEN: usize = 0xFF - 0x5;
+
+/// RRIP 'NM' entries (IEEE P1282 4.1.4), split in as many entries as needed
+pub fn write_nm(buff: &mut Vec<u8>, name: &str) -> std::io::Result<()> {
+    let chunks: Vec<&[u8]> = name.as_bytes().chunks(NM_MAX_LEN).collect();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        buff.write_all(b"NM")?;
+        buff.write_u8((0x5 + chunk.len()).try_into().unwrap())?;
+        buff.write_u8(0x1)?;
+
+        // CONTINUE if the name goes on in the next entry
+        buff.write_u8(if index + 1 == chunks.len() { 0x0 } else { 0x1 })?;
+        buff.write_all(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Maximum size of the component records of a 'SL' entry
+const SL_MAX_LEN: usize = 0xFF - 0x5;
+
+/// RRIP 'SL' entries (IEEE P1282 4.1.3) for a symbolic link to `target`,
+/// components are split between entries so every entry is filled, like
+/// mkisofs does
+pub fn write_sl(buff: &mut Vec<u8>, target: &str) -> std::io::Result<()> {
+    // Component flags and content
+    let mut components: Vec<(u8, &[u8])> = Vec::new();
+    if target.starts_with('/') {
+        // ROOT
+        components.push((0x8, b""));
+    }
+
+    for component in target.split('/').filter(|x| !x.is_empty()) {
+        match component {
+            // CURRENT
+            "." => components.push((0x2, b"")),
+            // PARENT
+            ".." => components.push((0x4, b"")),
+            _ => components.push((0x0, component.as_bytes())),
+        }
+    }
+
+    let component_count = components.len();
+    let mut entries: Vec<Vec<u8>> = vec![Vec::new()];
+    for (index, (flags, mut content)) in components.into_iter().enumerate() {
+        loop {
+            let entry = entries.last_mut().unwrap();
+            let available = SL_MAX_LEN - entry.len();
+
+            // Keep at least one byte of content with the record header
+            let needed = if content.is_empty() { 2 } else { 3 };
+            if available < needed {
+                entries.push(Vec::new());
+                continue;
+            }
+
+            let mut len = content.len().min(available - 2);
+
+            // libarchive expects entries to end in the middle of a component,
+            // don't fill the entry with the end of this one
+            let is_last = index + 1 == component_count;
+            if len == content.len()
+                && len > 1
+                && !is_last
+                && available - len < 5
+            {
+                len -= 1;
+            }
+
+            let (chunk, rest) = content.split_at(len);
+
+            // CONTINUE if the component goes on in the next record
+            let flags = if rest.is_empty() { flags } else { 0x1 };
+            entry.push(flags);
+            entry.push(len.try_into().unwrap());
+            entry.extend_from_slice(chunk);
+
+            content = rest;
+            if content.is_empty() {
+                break;
+            }
+        }
+    }
+
+    let entry_count = entries.len();
+    for (index, entry) in entries.into_iter().enumerate() {
+        buff.write_all(b"SL")?;
+        buff.write_u8((0x5 + entry.len()).try_into().unwrap())?;
+        buff.write_u8(0x1)?;
+
+        // CONTINUE if the link goes on in the next entry
+        buff.write_u8(if index + 1 == entry_count { 0x0 } else { 0x1 })?;
+        buff.write_all(&entry)?;
+    }
+
+    Ok(())
+}
+
+/// zisofs 'ZF' entry of a file stored compressed, whose content is
+/// `uncompressed_size` bytes long once decompressed
+pub fn write_zf(
+    buff: &mut Vec<u8>,
+    uncompressed_size: u32,
+) -> std::io::Result<()> {
+    buff.write_all(b"ZF")?;
+    buff.write_u8(0x10)?;
+    buff.write_u8(0x1)?;
+    // "paged zlib" algorithm
+    buff.write_all(b"pz")?;
+    buff.write_u8((zisofs::HEADER_SIZE / 4).try_into().unwrap())?;
+    buff.write_u8(zisofs::LOG2_BLOCK_SIZE)?;
+
+    write_bothendian! {
+        buff.write_u32(uncompressed_size)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use crate::checksum;
+use crate::directory_entry::{DirectoryEntry, MAX_DIRECTORY_LEVEL};
+use crate::error::{IsoError, Result};
+use crate::file_entry::{FileEntry, FileType};
+use crate::option::Opt;
+use crate::reader::{DirectoryRecord, IsoDirectory, IsoFile, IsoImage};
+use crate::rock_ridge::PosixAttributes;
+use crate::utils::{LOGIC_SIZE, LOGIC_SIZE_U32};
+
+use chrono::prelude::*;
+
+use std::io;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+
+/// Sessions start on a multiple of 16 logical blocks, the 32 KiB ECC blocks
+/// of DVDs, like growisofs does
+const SESSION_ALIGNMENT_IN_LB: u32 = 0x10;
+
+/// Attributes, modification time and flags of an imported record
+fn import_record(
+    record: &DirectoryRecord,
+    posix: &mut PosixAttributes,
+    timestamp: &mut DateTime<Utc>,
+    hidden: &mut bool,
+) {
+    if let Some(attributes) = &record.rock_ridge.posix {
+        *posix = PosixAttributes {
+            mode: attributes.mode,
+            uid: attributes.uid,
+            gid: attributes.gid,
+        };
+    }
+    if let Some(modified) = record.modified() {
+        *timestamp = modified.with_timezone(&Utc);
+    }
+    *hidden = record.flags & 0x1 != 0;
+}
+
+fn import_file<R>(
+    image: &mut IsoImage<R>,
+    file: &IsoFile,
+    in_relocated: bool,
+) -> io::Result<FileEntry>
+where
+    R: Read + Seek,
+{
+    let record = &file.record;
+    let mut res = if let Some(target) = &record.rock_ridge.symlink {
+        FileEntry::new(
+            FileType::Symlink {
+                name: file.name.clone(),
+                target: target.clone(),
+            },
+            0,
+        )
+    } else if in_relocated {
+        // libarchive names the files when reaching their content, which
+        // must then follow the placeholders of the relocated directories
+        let mut data = Vec::new();
+        image.open_file(file).read_to_end(&mut data)?;
+        let size = data.len();
+        FileEntry::new(
+            FileType::Buffer {
+                name: file.name.clone(),
+                data,
+            },
+            size,
+        )
+    } else {
+        FileEntry::new(
+            FileType::Imported {
+                name: file.name.clone(),
+                extents: file.extents.clone(),
+                uncompressed_size: record
+                    .rock_ridge
+                    .zisofs
+                    .map(|zisofs| zisofs.uncompressed_size),
+            },
+            file.stored_size().try_into().unwrap(),
+        )
+    };
+
+    import_record(record, &mut res.posix, &mut res.timestamp, &mut res.hidden);
+    res.associated = record.flags & 0x4 != 0;
+    Ok(res)
+}
+
+fn import_directory<R>(
+    image: &mut IsoImage<R>,
+    directory: &IsoDirectory,
+    level: usize,
+) -> io::Result<DirectoryEntry>
+where
+    R: Read + Seek,
+{
+    let mut res = DirectoryEntry::new()?;
+    res.name = directory.name.clone();
+    import_record(
+        &directory.record,
+        &mut res.posix,
+        &mut res.timestamp,
+        &mut res.hidden,
+    );
+
+    for child in &directory.directories {
+        res.dir_childs
+            .push(import_directory(image, child, level + 1)?);
+    }
+    for child in &directory.files {
+        res.files_childs.push(import_file(
+            image,
+            child,
+            level > MAX_DIRECTORY_LEVEL,
+        )?);
+    }
+
+    Ok(res)
+}
+
+/// Tree of the entries of the last session of `image`, the files keeping
+/// their content where it is. Relocated directories are back at their
+/// original place and are relocated again if needed, their files being
+/// written again.
+pub fn import_tree<R>(image: &mut IsoImage<R>) -> io::Result<DirectoryEntry>
+where
+    R: Read + Seek,
+{
+    let root = image.root.clone();
+    import_directory(image, &root, 1)
+}
+
+/// Make sure `opt` doesn't touch the system area or the El Torito boot,
+/// which stay the ones of the first session
+pub fn check_options(opt: &Opt) -> Result<()> {
+    let unsupported = [
+        (opt.eltorito_opt.has_boot_catalog(), "El Torito boot"),
+        (
+            opt.embedded_boot.is_some() || opt.grub2_mbr.is_some(),
+            "embedded boot",
+        ),
+        (opt.protective_msdos_label, "protective MBR"),
+        (opt.gpt, "GPT"),
+    ];
+
+    match unsupported.into_iter().find(|&(used, _)| used) {
+        Some((_, option)) => Err(IsoError::UnsupportedInSession(option)),
+        None => Ok(()),
+    }
+}
+
+/// First logical block of a new session, after the volume and anything
+/// appended to the image
+pub fn get_next_session_lba<R>(image: &mut R) -> Result<u32>
+where
+    R: Read + Seek,
+{
+    let (_, _, volume_size) = checksum::read_primary(image)?;
+    let image_size = image.seek(SeekFrom::End(0))?;
+
+    u32::try_from(
+        volume_size
+            .max(image_size)
+            .div_ceil(u64::from(LOGIC_SIZE_U32)),
+    )
+    .ok()
+    .and_then(|x| x.checked_next_multiple_of(SESSION_ALIGNMENT_IN_LB))
+    .ok_or(IsoError::ImageTooLarge)
+}
+
+/// El Torito Boot Record of the volume descriptors at the start of `image`,
+/// if any
+fn read_boot_record<F>(image: &mut F) -> Result<Option<Vec<u8>>>
+where
+    F: Read + Seek,
+{
+    image.seek(SeekFrom::Start(0x10 * u64::from(LOGIC_SIZE_U32)))?;
+
+    loop {
+        let mut buff = vec![0; LOGIC_SIZE];
+        image.read_exact(&mut buff)?;
+
+        match buff[0] {
+            0 if buff[7..30] == *b"EL TORITO SPECIFICATION" => {
+                return Ok(Some(buff));
+            }
+            // Set terminator
+            0xFF => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Copy the volume descriptors of the session starting at `session_lba` over
+/// the ones of the first session, where readers without multisession
+/// support, and every reader of an image file, look for them. The El Torito
+/// Boot Record of the first session is kept right after the primary volume
+/// descriptor, at logical block 17 where firmwares look for it, its boot
+/// catalog staying where it is. A longer set of descriptors overwrites the
+/// path tables of the first session, which are no longer referenced.
+pub fn copy_volume_descriptors<F>(image: &mut F, session_lba: u32) -> Result<()>
+where
+    F: Read + Write + Seek,
+{
+    let boot_record = read_boot_record(image)?;
+
+    image.seek(SeekFrom::Start(
+        u64::from(session_lba + 0x10) * u64::from(LOGIC_SIZE_U32),
+    ))?;
+
+    let mut descriptors = Vec::new();
+    loop {
+        let mut buff = vec![0; LOGIC_SIZE];
+        image.read_exact(&mut buff)?;
+
+        // Set terminator
+        let is_last = buff[0] == 0xFF;
+        descriptors.extend(buff);
+        if is_last {
+            break;
+        }
+    }
+
+    if let Some(boot_record) = boot_record {
+        descriptors.splice(LOGIC_SIZE..LOGIC_SIZE, boot_record);
+    }
+
+    image.seek(SeekFrom::Start(0x10 * u64::from(LOGIC_SIZE_U32)))?;
+    image.write_all(&descriptors)?;
+
+    Ok(())
+}
@@ -1,12 +1,27 @@
+use crate::error::{IsoError, Result};
+use crate::option::{InterchangeLevel, is_d_character};
+
 use byteorder::WriteBytesExt;
-use std::io::Write;
+use chrono::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
 
 pub const LOGIC_SIZE: usize = 0x800;
-pub const LOGIC_SIZE_I64: i64 = 0x800;
 pub const LOGIC_SIZE_U32: u32 = 0x800;
 pub const SECTOR_SIZE: u32 = 0x200;
 pub const LOGIC_SIZE_U16: u16 = 0x800;
 
+/// Directory hierarchy being described: the ISO 9660 one (with Rock Ridge) or
+/// the Joliet one from the supplementary volume descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hierarchy {
+    Primary,
+    Joliet,
+}
+
 pub fn align_up_i32(value: i32, padding: i32) -> i32 {
     (value + (padding - 1)) & -padding
 }
@@ -15,72 +30,424 @@ pub fn align_up_u32(value: u32, padding: u32) -> u32 {
     value.next_multiple_of(padding)
 }
 
-/// Convert filename to DOS-style: <name>.<ext> where <name> is max 8 bytes long
-/// and <ext> is max 3 bytes long
-pub fn convert_name(value: &str) -> Vec<u8> {
-    let (name, ext) = value.split_once('.').unwrap_or((value, ""));
+/// Fill `buff` as much as possible, only reading less at the end of `reader`
+pub fn read_full<R>(reader: &mut R, buff: &mut [u8]) -> io::Result<usize>
+where
+    R: Read,
+{
+    let mut len = 0;
+    while len < buff.len() {
+        match reader.read(&mut buff[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(len)
+}
+
+/// Match a path of the image against a glob pattern: `?` matches any
+/// character and `*` any sequence of characters except '/', `**` matches
+/// across directories
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            ['*', '*', rest @ ..] => {
+                // "**/" also matches no directory at all
+                (0..=path.len()).any(|i| matches(rest, &path[i..]))
+                    || (rest.first() == Some(&'/') && matches(&rest[1..], path))
+            }
+            ['*', rest @ ..] => {
+                let len = path.iter().position(|c| *c == '/');
+                (0..=len.unwrap_or(path.len()))
+                    .any(|i| matches(rest, &path[i..]))
+            }
+            ['?', rest @ ..] => {
+                path.first().is_some_and(|c| *c != '/')
+                    && matches(rest, &path[1..])
+            }
+            [c, rest @ ..] => {
+                path.first() == Some(c) && matches(rest, &path[1..])
+            }
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches(&pattern, &path)
+}
+
+/// File name of a host path, as used in the image
+pub fn get_host_file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_string())
+        .ok_or_else(|| IsoError::InvalidFileName(path.to_path_buf()))
+}
+
+/// Maximum lengths of a file or directory identifier at a given interchange
+/// level (ECMA-119 7.5 and 7.6): name, extension and both of them together
+fn identifier_limits(
+    level: InterchangeLevel,
+    is_directory: bool,
+) -> (usize, usize, usize) {
+    match (level, is_directory) {
+        (InterchangeLevel::Level1, false) => (8, 3, 11),
+        (InterchangeLevel::Level1, true) => (8, 0, 8),
+        (_, false) => (30, 30, 30),
+        (_, true) => (31, 0, 31),
+    }
+}
+
+/// Uppercase `value` and replace everything that isn't a d-character
+/// (`A-Z`, `0-9` and `_`) by an underscore
+fn to_d_characters(value: &str) -> Vec<u8> {
+    value
+        .chars()
+        .map(|c| c.to_ascii_uppercase())
+        .map(|c| {
+            if is_d_character(c) {
+                u8::try_from(c).unwrap()
+            } else {
+                b'_'
+            }
+        })
+        .collect()
+}
+
+/// Convert filename to an ISO 9660 identifier of the given interchange level:
+/// <name>.<ext> for files (with at most 8 and 3 characters at level 1) and
+/// <name> for directories, using only d-characters
+pub fn convert_name(
+    value: &str,
+    level: InterchangeLevel,
+    is_directory: bool,
+) -> (Vec<u8>, Vec<u8>) {
+    let (name, ext) = match value.rsplit_once('.') {
+        Some((name, ext)) if !is_directory => (name, ext),
+        _ => (value, ""),
+    };
+    let mut name = to_d_characters(name);
+    let mut ext = to_d_characters(ext);
+
+    let (name_max, ext_max, total_max) = identifier_limits(level, is_directory);
+    // Always leave room for 8 characters of name
+    ext.truncate(ext_max.min(total_max - 8));
+    name.truncate(name_max.min(total_max - ext.len()));
+
+    if name.is_empty() && ext.is_empty() {
+        name.push(b'_');
+    }
+
+    (name, ext)
+}
+
+/// Give unique ISO 9660 identifiers to the entries of a directory, adding a
+/// `_<n>` suffix, made of d-characters too, to the names that would collide
+/// once converted
+pub struct NameMapper {
+    level: InterchangeLevel,
+    used: HashSet<Vec<u8>>,
+}
+
+impl NameMapper {
+    pub fn new(level: InterchangeLevel) -> NameMapper {
+        NameMapper {
+            level,
+            used: HashSet::new(),
+        }
+    }
+
+    /// Identifier of `value`, without the version of files but with their
+    /// '.' separator
+    pub fn map(&mut self, value: &str, is_directory: bool) -> Vec<u8> {
+        let (name, ext) = convert_name(value, self.level, is_directory);
+        let (name_max, _, total_max) =
+            identifier_limits(self.level, is_directory);
+        let name_max = name_max.min(total_max - ext.len());
+
+        let mut candidate = name.clone();
+        let mut count = 0u32;
+        loop {
+            let mut key = candidate.clone();
+            if !ext.is_empty() {
+                key.push(b'.');
+                key.extend(&ext);
+            }
+
+            if self.used.insert(key.clone()) {
+                if !is_directory && ext.is_empty() {
+                    key.push(b'.');
+                }
+                return key;
+            }
+
+            count += 1;
+            let suffix = format!("_{count}");
+            candidate = name.clone();
+            candidate.truncate(name_max - suffix.len());
+            candidate.extend(suffix.as_bytes());
+        }
+    }
+}
+
+/// Maximum length of a Joliet identifier, in UCS-2 characters
+pub const JOLIET_MAX_NAME_LEN: usize = 64;
+
+/// Replace the characters forbidden in Joliet identifiers, and the ones out
+/// of UCS-2, by an underscore
+fn to_joliet_char(c: char) -> char {
+    match c {
+        '*' | '/' | ':' | ';' | '?' | '\\' => '_',
+        // Outside of the Basic Multilingual Plane, not representable
+        c if u32::from(c) > 0xFFFF => '_',
+        c => c,
+    }
+}
+
+/// Encode characters of the Basic Multilingual Plane in UCS-2 big endian
+fn to_ucs2(value: impl IntoIterator<Item = char>) -> Vec<u8> {
+    value
+        .into_iter()
+        .flat_map(|c| {
+            let mut buf = [0u16; 2];
+            c.encode_utf16(&mut buf);
+            buf[0].to_be_bytes()
+        })
+        .collect()
+}
+
+/// Convert filename to a Joliet identifier: UCS-2 big endian, truncated to 64
+/// characters and without the characters forbidden by the specification
+pub fn convert_joliet_name(value: &str) -> Vec<u8> {
+    to_ucs2(value.chars().map(to_joliet_char).take(JOLIET_MAX_NAME_LEN))
+}
+
+/// Give unique Joliet identifiers to the entries of a directory, truncating
+/// the names before their extension and adding a `_<n>` suffix to the ones
+/// that would collide once converted
+#[derive(Default)]
+pub struct JolietNameMapper {
+    used: HashSet<Vec<u8>>,
+}
+
+impl JolietNameMapper {
+    pub fn new() -> JolietNameMapper {
+        JolietNameMapper::default()
+    }
+
+    /// Identifier of `value`, without the version of files
+    pub fn map(&mut self, value: &str, is_directory: bool) -> Vec<u8> {
+        let chars: Vec<char> = value.chars().map(to_joliet_char).collect();
+        let (name, mut ext) = match chars.iter().rposition(|c| *c == '.') {
+            Some(index) if !is_directory => {
+                (&chars[..index], chars[index..].to_vec())
+            }
+            _ => (&chars[..], Vec::new()),
+        };
+        // Always leave room for 8 characters of name
+        ext.truncate(JOLIET_MAX_NAME_LEN - 8);
+
+        let mut suffix = String::new();
+        let mut count = 0u32;
+        loop {
+            let name_max = JOLIET_MAX_NAME_LEN - ext.len() - suffix.len();
+            let candidate = to_ucs2(
+                name.iter()
+                    .take(name_max)
+                    .copied()
+                    .chain(suffix.chars())
+                    .chain(ext.iter().copied()),
+            );
+
+            if self.used.insert(candidate.clone()) {
+                return candidate;
+            }
 
-    let file_truncated_size = name.len().min(8);
-    let extension_truncated_size = ext.len().min(3);
+            count += 1;
+            suffix = format!("_{count}");
+        }
+    }
+}
+
+/// Order of the file identifiers in a directory (ECMA-119 9.3): name and
+/// extension padded with spaces, then version from the highest. Joliet
+/// identifiers are compared by UCS-2 character.
+pub fn compare_identifiers(
+    a: &[u8],
+    b: &[u8],
+    hierarchy: Hierarchy,
+) -> Ordering {
+    fn characters(value: &[u8], hierarchy: Hierarchy) -> Vec<u16> {
+        match hierarchy {
+            Hierarchy::Primary => {
+                value.iter().copied().map(u16::from).collect()
+            }
+            Hierarchy::Joliet => value
+                .chunks(2)
+                .map(|x| {
+                    u16::from_be_bytes([x[0], x.get(1).copied().unwrap_or(0)])
+                })
+                .collect(),
+        }
+    }
+
+    fn split(value: &[u16]) -> (&[u16], &[u16], &[u16]) {
+        let (name, version) =
+            match value.iter().position(|x| *x == u16::from(b';')) {
+                Some(index) => (&value[..index], &value[index + 1..]),
+                None => (value, &[][..]),
+            };
+        match name.iter().position(|x| *x == u16::from(b'.')) {
+            Some(index) => (&name[..index], &name[index + 1..], version),
+            None => (name, &[][..], version),
+        }
+    }
+
+    fn compare_padded(a: &[u16], b: &[u16]) -> Ordering {
+        let len = a.len().max(b.len());
+        let padded = |value: &[u16]| {
+            value
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(u16::from(b' ')))
+                .take(len)
+                .collect::<Vec<u16>>()
+        };
+        padded(a).cmp(&padded(b))
+    }
+
+    let a = characters(a, hierarchy);
+    let b = characters(b, hierarchy);
+    let (a_name, a_extension, a_version) = split(&a);
+    let (b_name, b_extension, b_version) = split(&b);
+    compare_padded(a_name, b_name)
+        .then_with(|| compare_padded(a_extension, b_extension))
+        .then_with(|| compare_padded(b_version, a_version))
+}
+
+/// Write `value` in a fixed size volume descriptor field, padded with spaces
+pub fn write_padded_str<T>(
+    output_writter: &mut T,
+    value: &[u8],
+    len: usize,
+    hierarchy: Hierarchy,
+) -> std::io::Result<()>
+where
+    T: Write,
+{
+    let mut buff: Vec<u8> = match hierarchy {
+        Hierarchy::Primary => value.iter().copied().take(len).collect(),
+        Hierarchy::Joliet => {
+            let mut res = convert_joliet_name(&String::from_utf8_lossy(value));
+            res.truncate(len - len % 2);
+            res
+        }
+    };
 
-    let mut result = name.as_bytes()[0..file_truncated_size].to_vec();
-    if extension_truncated_size != 0 {
-        result.push(b'.');
-        result.extend(&ext.as_bytes()[0..extension_truncated_size]);
+    while buff.len() < len {
+        match hierarchy {
+            Hierarchy::Joliet
+                if buff.len().is_multiple_of(2) && buff.len() + 1 < len =>
+            {
+                buff.extend([0x0, b' ']);
+            }
+            Hierarchy::Joliet => buff.push(0x0),
+            Hierarchy::Primary => buff.push(b' '),
+        }
     }
 
-    result
+    output_writter.write_all(&buff)
 }
 
+/// Size of a directory or path table record, `system_use_size` being the
+/// size of its SUSP entries
 pub fn get_entry_size(
     base_size: u32,
-    file_name: &str,
+    file_identifier: &[u8],
     directory_type: u32,
     padding_type: usize,
+    system_use_size: usize,
 ) -> u32 {
-    let file_name_len = file_name.len();
-    if file_name_len > 251 {
-        panic!(
-            "File name \"{}\" is too big (max size is 251 bytes)",
-            file_name
-        );
-    }
-
-    let file_name_corrected = convert_name(file_name);
     let file_identifier = match directory_type {
         1 | 3 | 5 => &[0u8],
         2 => &[1u8],
-        _ => &file_name_corrected[..],
+        _ => file_identifier,
     };
 
     let mut file_identifier_len = file_identifier.len();
-    let mut system_use_field_size = 0;
 
     if file_identifier_len % 2 != padding_type {
         file_identifier_len += 1;
     }
 
-    // is not a path entry calculation
-    if directory_type < 5 {
-        // Rock Ridge 'PX' entry
-        system_use_field_size += 0x2c;
-    }
+    base_size
+        + u32::try_from(file_identifier_len).unwrap()
+        + u32::try_from(system_use_size).unwrap()
+}
 
-    // regular directory/file
-    if directory_type == 0 {
-        // Rock Ridge 'NM' entry
-        system_use_field_size += 0x5;
-        system_use_field_size += u32::try_from(file_name_len).unwrap();
-    }
+/// Sum of the 16 bits little endian words of an El Torito validation entry,
+/// which is 0 for a valid entry
+pub fn eltorito_checksum(data: &[u8]) -> u16 {
+    data.chunks(2)
+        .map(|x| u16::from_le_bytes([x[0], x.get(1).copied().unwrap_or(0)]))
+        .fold(0, u16::wrapping_add)
+}
 
-    // root '.' has CE and SP of SUSP
-    if directory_type == 3 {
-        system_use_field_size += 0x7 + 0x1c; // SUSP 'SP' + SUSP 'CE'
+/// Sum of the 32 bits little endian words of a boot image after its boot info
+/// table (mkisofs convention), the end of the image is padded with zeros
+pub fn boot_info_table_checksum(data: &[u8]) -> u32 {
+    data.get(0x40..)
+        .unwrap_or_default()
+        .chunks(4)
+        .map(|x| {
+            let mut word = [0; 4];
+            word[..x.len()].copy_from_slice(x);
+            u32::from_le_bytes(word)
+        })
+        .fold(0, u32::wrapping_add)
+}
+
+/// CRC-32 (IEEE 802.3) of `data`, as used by the GPT headers
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
     }
 
-    base_size
-        + u32::try_from(file_identifier_len).unwrap()
-        + system_use_field_size
+    !crc
+}
+
+/// Write a 7 bytes directory record date (ECMA-119 9.1.5)
+pub fn write_record_datetime<T>(
+    output_writter: &mut T,
+    record_datetime: &DateTime<Utc>,
+) -> std::io::Result<()>
+where
+    T: Write,
+{
+    output_writter
+        .write_u8((record_datetime.year() - 1900).try_into().unwrap())?;
+    output_writter.write_u8(record_datetime.month().try_into().unwrap())?;
+    output_writter.write_u8(record_datetime.day().try_into().unwrap())?;
+    output_writter.write_u8(record_datetime.hour().try_into().unwrap())?;
+    output_writter.write_u8(record_datetime.minute().try_into().unwrap())?;
+    output_writter.write_u8(record_datetime.second().try_into().unwrap())?;
+
+    // GMT offset
+    output_writter.write_u8(0u8)?;
+
+    Ok(())
 }
 
 pub fn write_lba_to_cls<T>(
@@ -0,0 +1,895 @@
+use crate::directory_entry::MAX_DIRECTORY_LEVEL;
+use crate::option;
+use crate::reader::PathTableRecord;
+use crate::utils::{self, Hierarchy, LOGIC_SIZE, LOGIC_SIZE_U32};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+
+/// Continuation areas followed for a single record, protects against loops
+const MAX_CONTINUATION_AREAS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Readers cope with it, but the image isn't strictly conformant
+    Warning,
+    /// Readers may fail or show something else than what was written
+    Error,
+}
+
+/// Problem found in an image by [verify]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Logical block holding the faulty structure, if any
+    pub lba: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        match self.lba {
+            Some(lba) => write!(f, "{severity} at LBA {lba}: {}", self.message),
+            None => write!(f, "{severity}: {}", self.message),
+        }
+    }
+}
+
+/// Volume descriptor fields needed to check a hierarchy
+struct Volume {
+    hierarchy: Hierarchy,
+    /// Location of the volume descriptor
+    lba: u32,
+    volume_size: u32,
+    path_table_size: u32,
+    path_table_l_lba: u32,
+    path_table_m_lba: u32,
+    root_lba: u32,
+    root_size: u32,
+}
+
+/// Directory found while walking a hierarchy
+struct Directory {
+    identifier: Vec<u8>,
+    lba: u32,
+    size: u32,
+    parent_lba: u32,
+    parent_index: u16,
+    level: usize,
+}
+
+/// Directory record whose system use area is checked
+struct Record {
+    /// Location of the record itself
+    lba: u32,
+    /// Location of the directory holding the record
+    directory_lba: u32,
+    /// Location of the extent described by the record
+    extent_lba: u32,
+    /// Position of the record in its directory, 0 for '.' and 1 for '..'
+    index: usize,
+    is_root: bool,
+}
+
+/// Rock Ridge relocation entries found in the primary hierarchy
+#[derive(Default)]
+struct RelocationLinks {
+    /// Directory holding the 'CL' placeholder, by relocated directory
+    child_links: HashMap<u32, u32>,
+    /// 'PL' entry of the '..' record, by directory
+    parent_links: HashMap<u32, u32>,
+    /// Directories with a 'RE' entry
+    relocated: HashSet<u32>,
+}
+
+/// Whether a primary hierarchy identifier only uses d-characters, and the '.'
+/// and ';' separators for files (ECMA-119 7.5 and 7.6)
+fn is_d_identifier(identifier: &[u8], is_directory: bool) -> bool {
+    identifier.iter().map(|&x| char::from(x)).all(|c| {
+        option::is_d_character(c) || (!is_directory && (c == '.' || c == ';'))
+    })
+}
+
+/// Whether the records are ordered by level, then by parent directory number
+/// and by identifier (ECMA-119 9.4). Parents coming before their children
+/// and in order, the levels are too.
+fn is_path_table_ordered(
+    path_table: &[PathTableRecord],
+    hierarchy: Hierarchy,
+) -> bool {
+    let parents_first = path_table
+        .iter()
+        .enumerate()
+        .skip(1)
+        .all(|(index, x)| usize::from(x.parent_index) <= index);
+
+    // The root identifier (0) comes before any other
+    parents_first
+        && path_table.windows(2).all(|x| {
+            x[0].parent_index < x[1].parent_index
+                || (x[0].parent_index == x[1].parent_index
+                    && utils::compare_identifiers(
+                        &x[0].identifier,
+                        &x[1].identifier,
+                        hierarchy,
+                    ) == Ordering::Less)
+        })
+}
+
+struct Verifier<R> {
+    reader: R,
+    /// Size of the image in bytes
+    image_size: u64,
+    volume_size: u32,
+    /// Number of bytes to skip at the start of every system use area, `None`
+    /// if the image doesn't use SUSP
+    susp_skip: Option<usize>,
+    has_er: bool,
+    links: RelocationLinks,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<R> Verifier<R>
+where
+    R: Read + Seek,
+{
+    fn report(
+        &mut self,
+        severity: Severity,
+        lba: Option<u32>,
+        message: impl Into<String>,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            lba,
+            message: message.into(),
+        });
+    }
+
+    fn error(&mut self, lba: u32, message: impl Into<String>) {
+        self.report(Severity::Error, Some(lba), message);
+    }
+
+    fn warning(&mut self, lba: u32, message: impl Into<String>) {
+        self.report(Severity::Warning, Some(lba), message);
+    }
+
+    fn read(&mut self, lba: u32, len: usize) -> Option<Vec<u8>> {
+        let start = u64::from(lba) * u64::from(LOGIC_SIZE_U32);
+        if start + u64::try_from(len).unwrap() > self.image_size {
+            self.error(lba, "read past the end of the image");
+            return None;
+        }
+
+        let mut buff = vec![0; len];
+        let res = self
+            .reader
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| self.reader.read_exact(&mut buff));
+        match res {
+            Ok(()) => Some(buff),
+            Err(err) => {
+                self.error(lba, format!("I/O error: {err}"));
+                None
+            }
+        }
+    }
+
+    /// Little endian value of a both-endian field (ECMA-119 7.2.3)
+    fn both_u16(&mut self, lba: u32, data: &[u8], field: &str) -> u16 {
+        let value = LittleEndian::read_u16(data);
+        if BigEndian::read_u16(&data[2..]) != value {
+            self.error(lba, format!("both-endian {field} halves differ"));
+        }
+        value
+    }
+
+    /// Little endian value of a both-endian field (ECMA-119 7.3.3)
+    fn both_u32(&mut self, lba: u32, data: &[u8], field: &str) -> u32 {
+        let value = LittleEndian::read_u32(data);
+        if BigEndian::read_u32(&data[4..]) != value {
+            self.error(lba, format!("both-endian {field} halves differ"));
+        }
+        value
+    }
+
+    /// Make sure an extent ends in the volume
+    fn check_extent(&mut self, record_lba: u32, lba: u32, size: u32) {
+        let end = u64::from(lba) * u64::from(LOGIC_SIZE_U32) + u64::from(size);
+        if end > u64::from(self.volume_size) * u64::from(LOGIC_SIZE_U32) {
+            self.error(
+                record_lba,
+                format!("extent at LBA {lba} ends outside of the volume"),
+            );
+        }
+    }
+
+    fn verify_volume_descriptors(&mut self) -> Vec<Volume> {
+        let mut res = Vec::new();
+
+        let mut lba = 0x10;
+        loop {
+            let Some(data) = self.read(lba, LOGIC_SIZE) else {
+                return res;
+            };
+            if &data[1..6] != b"CD001" {
+                self.error(lba, "missing volume descriptor set terminator");
+                return res;
+            }
+            if data[6] != 0x1 {
+                self.error(lba, "invalid volume descriptor version");
+            }
+
+            match data[0] {
+                1 => {
+                    res.push(self.verify_volume(lba, &data, Hierarchy::Primary))
+                }
+                // Joliet escape sequences (UCS-2 Level 1, 2 or 3)
+                2 if data[88..90] == *b"%/"
+                    && matches!(data[90], b'@' | b'C' | b'E') =>
+                {
+                    res.push(self.verify_volume(lba, &data, Hierarchy::Joliet))
+                }
+                0xff => return res,
+                _ => {}
+            }
+
+            lba += 1;
+        }
+    }
+
+    fn verify_volume(
+        &mut self,
+        lba: u32,
+        data: &[u8],
+        hierarchy: Hierarchy,
+    ) -> Volume {
+        let volume_size = self.both_u32(lba, &data[80..], "volume space size");
+        self.both_u16(lba, &data[120..], "volume set size");
+        self.both_u16(lba, &data[124..], "volume sequence number");
+        let logical_block_size =
+            self.both_u16(lba, &data[128..], "logical block size");
+        if usize::from(logical_block_size) != LOGIC_SIZE {
+            self.error(
+                lba,
+                format!("unsupported logical block size {logical_block_size}"),
+            );
+        }
+        let path_table_size =
+            self.both_u32(lba, &data[132..], "path table size");
+
+        // Root directory record
+        if data[156] != 0x22 || data[156 + 32] != 1 || data[156 + 33] != 0 {
+            self.error(lba, "invalid root directory record");
+        }
+        let root_lba = self.both_u32(lba, &data[158..], "root extent location");
+        let root_size = self.both_u32(lba, &data[166..], "root data length");
+
+        if data[881] != 0x1 {
+            self.error(lba, "invalid file structure version");
+        }
+
+        Volume {
+            hierarchy,
+            lba,
+            volume_size,
+            path_table_size,
+            path_table_l_lba: LittleEndian::read_u32(&data[140..]),
+            path_table_m_lba: BigEndian::read_u32(&data[148..]),
+            root_lba,
+            root_size,
+        }
+    }
+
+    fn verify_hierarchy(&mut self, volume: &Volume) {
+        if volume.hierarchy == Hierarchy::Primary {
+            self.find_susp(volume.root_lba);
+        }
+
+        // Walking the directories level by level gives the path table order
+        let mut path_table = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([Directory {
+            identifier: vec![0],
+            lba: volume.root_lba,
+            size: volume.root_size,
+            parent_lba: volume.root_lba,
+            parent_index: 1,
+            level: 1,
+        }]);
+        while let Some(directory) = queue.pop_front() {
+            if !visited.insert(directory.lba) {
+                self.error(directory.lba, "directory loop");
+                continue;
+            }
+
+            path_table.push(PathTableRecord {
+                identifier: directory.identifier.clone(),
+                lba: directory.lba,
+                parent_index: directory.parent_index,
+            });
+            let index = u16::try_from(path_table.len()).unwrap_or(u16::MAX);
+
+            if directory.level > MAX_DIRECTORY_LEVEL {
+                // Joliet readers don't care about the depth limit
+                let severity = match volume.hierarchy {
+                    Hierarchy::Primary => Severity::Error,
+                    Hierarchy::Joliet => Severity::Warning,
+                };
+                self.report(
+                    severity,
+                    Some(directory.lba),
+                    format!(
+                        "directory nested deeper than {MAX_DIRECTORY_LEVEL} \
+                         levels"
+                    ),
+                );
+            }
+
+            for (identifier, lba, size) in
+                self.verify_directory(volume, &directory)
+            {
+                queue.push_back(Directory {
+                    identifier,
+                    lba,
+                    size,
+                    parent_lba: directory.lba,
+                    parent_index: index,
+                    level: directory.level + 1,
+                });
+            }
+        }
+
+        self.verify_path_tables(volume, &path_table);
+
+        if volume.hierarchy == Hierarchy::Primary {
+            self.verify_relocations();
+        }
+    }
+
+    /// SUSP 'SP' entry at the start of the root '.' record (IEEE P1281 5.3)
+    fn find_susp(&mut self, root_lba: u32) {
+        let Some(data) = self.read(root_lba, LOGIC_SIZE) else {
+            return;
+        };
+
+        let record_len = usize::from(data[0]);
+        if record_len < 0x22 + 7 {
+            return;
+        }
+
+        let sp = &data[0x22..record_len];
+        if &sp[0..2] == b"SP" {
+            if sp[2] != 7 || sp[4..6] != [0xBE, 0xEF] {
+                self.error(root_lba, "invalid SP entry");
+            }
+            self.susp_skip = Some(usize::from(sp[6]));
+        }
+    }
+
+    /// Check the records of a directory, giving back its subdirectories
+    fn verify_directory(
+        &mut self,
+        volume: &Volume,
+        directory: &Directory,
+    ) -> Vec<(Vec<u8>, u32, u32)> {
+        let lba = directory.lba;
+        let size = directory.size;
+        let mut res = Vec::new();
+
+        if size == 0 || !size.is_multiple_of(LOGIC_SIZE_U32) {
+            self.error(
+                lba,
+                "directory size isn't a multiple of the block size",
+            );
+        }
+        self.check_extent(lba, lba, size);
+        if u64::from(lba) + u64::from(size.div_ceil(LOGIC_SIZE_U32))
+            > u64::from(self.volume_size)
+        {
+            return res;
+        }
+
+        let Some(data) = self.read(lba, usize::try_from(size).unwrap()) else {
+            return res;
+        };
+
+        let mut index = 0;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut unsorted = false;
+        let mut pending_extent: Option<Vec<u8>> = None;
+
+        let mut offset = 0;
+        while offset < data.len() {
+            let block_end = (offset + 1).next_multiple_of(LOGIC_SIZE);
+            let record_lba = lba + u32::try_from(offset / LOGIC_SIZE).unwrap();
+
+            // Zero padding up to the next block
+            let record_len = usize::from(data[offset]);
+            if record_len == 0 {
+                offset = block_end;
+                continue;
+            }
+
+            if offset + record_len > block_end {
+                self.error(record_lba, "directory record crosses a block");
+                break;
+            }
+            if record_len < 0x22 {
+                self.error(record_lba, "directory record too short");
+                break;
+            }
+            if record_len % 2 != 0 {
+                self.error(record_lba, "odd directory record length");
+            }
+
+            let record = &data[offset..offset + record_len];
+            offset += record_len;
+
+            let identifier_len = usize::from(record[32]);
+            let identifier_end = 33 + identifier_len;
+            if identifier_len == 0 || identifier_end > record_len {
+                self.error(record_lba, "invalid file identifier length");
+                continue;
+            }
+            let identifier = record[33..identifier_end].to_vec();
+
+            let extent_lba =
+                self.both_u32(record_lba, &record[2..], "extent location");
+            let data_length =
+                self.both_u32(record_lba, &record[10..], "data length");
+            self.both_u16(record_lba, &record[28..], "volume sequence number");
+            let flags = record[25];
+
+            match index {
+                0 => {
+                    if identifier != [0]
+                        || extent_lba != lba
+                        || data_length != size
+                        || flags & 0x2 == 0
+                    {
+                        self.error(record_lba, "invalid '.' record");
+                    }
+                }
+                1 => {
+                    if identifier != [1]
+                        || extent_lba != directory.parent_lba
+                        || flags & 0x2 == 0
+                    {
+                        self.error(record_lba, "invalid '..' record");
+                    }
+                }
+                _ => {
+                    if identifier == [0] || identifier == [1] {
+                        self.error(record_lba, "misplaced '.' or '..' record");
+                    }
+
+                    // Records of a multi-extent file follow each other
+                    if let Some(pending) = pending_extent.take()
+                        && pending != identifier
+                    {
+                        self.error(
+                            record_lba,
+                            "multi-extent file without final record",
+                        );
+                    }
+                    if flags & 0x80 != 0 {
+                        pending_extent = Some(identifier.clone());
+                    }
+
+                    if volume.hierarchy == Hierarchy::Primary
+                        && !is_d_identifier(&identifier, flags & 0x2 != 0)
+                    {
+                        self.error(
+                            record_lba,
+                            "file identifier with other characters than \
+                             d-characters",
+                        );
+                    }
+
+                    if let Some(previous) = &previous
+                        && utils::compare_identifiers(
+                            previous,
+                            &identifier,
+                            volume.hierarchy,
+                        ) == Ordering::Greater
+                    {
+                        unsorted = true;
+                    }
+                    previous = Some(identifier.clone());
+
+                    if flags & 0x2 != 0 {
+                        res.push((identifier, extent_lba, data_length));
+                    } else {
+                        self.check_extent(record_lba, extent_lba, data_length);
+                    }
+                }
+            }
+
+            if volume.hierarchy == Hierarchy::Primary
+                && let Some(skip) = self.susp_skip
+            {
+                // padding if even
+                let system_use_start =
+                    identifier_end + (1 - identifier_len % 2);
+                let is_root = lba == volume.root_lba && index == 0;
+                // The root '.' record starts with the 'SP' entry
+                let skip = if is_root { 0 } else { skip };
+                let system_use =
+                    record.get(system_use_start + skip..).unwrap_or_default();
+                self.verify_system_use(
+                    &Record {
+                        lba: record_lba,
+                        directory_lba: lba,
+                        extent_lba,
+                        index,
+                        is_root,
+                    },
+                    system_use,
+                );
+            }
+
+            index += 1;
+        }
+
+        if index < 2 {
+            self.error(lba, "directory without '.' and '..' records");
+        }
+        if pending_extent.is_some() {
+            self.error(lba, "multi-extent file without final record");
+        }
+        if unsorted {
+            self.error(lba, "directory records aren't sorted by identifier");
+        }
+
+        res
+    }
+
+    /// Check the SUSP and Rock Ridge entries of a record, following its
+    /// continuation areas
+    fn verify_system_use(&mut self, record: &Record, system_use: &[u8]) {
+        let mut area = system_use.to_vec();
+        let mut area_lba = record.lba;
+        let mut continuation_count = 0;
+
+        loop {
+            let mut continuation = None;
+
+            let mut offset = 0;
+            while offset + 4 <= area.len() {
+                let entry_len = usize::from(area[offset + 2]);
+                if entry_len < 4 || offset + entry_len > area.len() {
+                    self.error(area_lba, "invalid system use entry length");
+                    break;
+                }
+
+                let entry = area[offset..offset + entry_len].to_vec();
+                offset += entry_len;
+
+                let signature = String::from_utf8_lossy(&entry[0..2]);
+                let valid = match &entry[0..2] {
+                    // SUSP 'CE' entry (IEEE P1281 5.1)
+                    b"CE" => {
+                        if entry_len == 28 {
+                            continuation = Some((
+                                self.both_u32(area_lba, &entry[4..], "CE LBA"),
+                                self.both_u32(
+                                    area_lba,
+                                    &entry[12..],
+                                    "CE offset",
+                                ),
+                                self.both_u32(
+                                    area_lba,
+                                    &entry[20..],
+                                    "CE length",
+                                ),
+                            ));
+                        }
+                        entry_len == 28
+                    }
+                    // SUSP 'SP' entry (IEEE P1281 5.3)
+                    b"SP" => {
+                        if !record.is_root {
+                            self.error(
+                                area_lba,
+                                "SP entry outside of the root",
+                            );
+                        }
+                        entry_len == 7
+                    }
+                    // SUSP 'ST' entry (IEEE P1281 5.4)
+                    b"ST" => break,
+                    // SUSP 'ER' entry (IEEE P1281 5.5)
+                    b"ER" => {
+                        if record.is_root {
+                            self.has_er = true;
+                        }
+                        entry_len >= 8
+                            && 8 + usize::from(entry[4])
+                                + usize::from(entry[5])
+                                + usize::from(entry[6])
+                                <= entry_len
+                    }
+                    // RRIP 'PX' entry (IEEE P1282 4.1.1)
+                    b"PX" => {
+                        let valid = entry_len == 36 || entry_len == 44;
+                        if valid {
+                            for field in (4..entry_len).step_by(8) {
+                                self.both_u32(
+                                    area_lba,
+                                    &entry[field..],
+                                    "PX field",
+                                );
+                            }
+                        }
+                        valid
+                    }
+                    // RRIP 'SL' entry (IEEE P1282 4.1.3)
+                    b"SL" => {
+                        let mut component = 5;
+                        while component + 2 <= entry_len {
+                            component += 2 + usize::from(entry[component + 1]);
+                        }
+                        entry_len >= 5 && component == entry_len
+                    }
+                    // RRIP 'NM' entry (IEEE P1282 4.1.4)
+                    b"NM" => entry_len >= 5 && entry[4] & !0x7 == 0,
+                    // RRIP 'CL' entry (IEEE P1282 4.1.5.1)
+                    b"CL" => {
+                        if entry_len == 12 {
+                            let lba =
+                                self.both_u32(area_lba, &entry[4..], "CL LBA");
+                            self.links
+                                .child_links
+                                .insert(lba, record.directory_lba);
+                        }
+                        entry_len == 12
+                    }
+                    // RRIP 'PL' entry (IEEE P1282 4.1.5.2)
+                    b"PL" => {
+                        if record.index != 1 {
+                            self.error(area_lba, "PL entry outside of '..'");
+                        }
+                        if entry_len == 12 {
+                            let lba =
+                                self.both_u32(area_lba, &entry[4..], "PL LBA");
+                            self.links
+                                .parent_links
+                                .insert(record.directory_lba, lba);
+                        }
+                        entry_len == 12
+                    }
+                    // RRIP 'RE' entry (IEEE P1282 4.1.5.3)
+                    b"RE" => {
+                        self.links.relocated.insert(record.extent_lba);
+                        entry_len == 4
+                    }
+                    // RRIP 'TF' entry (IEEE P1282 4.1.6)
+                    b"TF" => {
+                        let flags = entry.get(4).copied().unwrap_or_default();
+                        let count =
+                            usize::try_from((flags & 0x7F).count_ones())
+                                .unwrap();
+                        let size = if flags & 0x80 != 0 { 17 } else { 7 };
+                        entry_len == 5 + count * size
+                    }
+                    // zisofs 'ZF' entry
+                    b"ZF" => {
+                        if entry_len == 16 {
+                            self.both_u32(
+                                area_lba,
+                                &entry[8..],
+                                "ZF uncompressed size",
+                            );
+                        }
+                        entry_len == 16
+                            && &entry[4..6] == b"pz"
+                            && (15..=17).contains(&entry[7])
+                    }
+                    _ => true,
+                };
+
+                if !valid {
+                    self.error(area_lba, format!("invalid {signature} entry"));
+                }
+            }
+
+            let Some((lba, ce_offset, len)) = continuation else {
+                return;
+            };
+
+            continuation_count += 1;
+            if continuation_count > MAX_CONTINUATION_AREAS {
+                self.error(record.lba, "too many continuation areas");
+                return;
+            }
+
+            let ce_offset = usize::try_from(ce_offset).unwrap();
+            let len = usize::try_from(len).unwrap();
+            if ce_offset + len > LOGIC_SIZE {
+                self.error(lba, "continuation area crosses a block");
+                return;
+            }
+            if lba >= self.volume_size {
+                self.error(
+                    record.lba,
+                    "continuation area outside of the volume",
+                );
+                return;
+            }
+
+            let Some(data) = self.read(lba, LOGIC_SIZE) else {
+                return;
+            };
+            area = data[ce_offset..ce_offset + len].to_vec();
+            area_lba = lba;
+        }
+    }
+
+    /// Compare both path tables with the directories found in the hierarchy
+    fn verify_path_tables(
+        &mut self,
+        volume: &Volume,
+        directories: &[PathTableRecord],
+    ) {
+        let size = usize::try_from(volume.path_table_size).unwrap();
+        let Some(data_l) = self.read(volume.path_table_l_lba, size) else {
+            return;
+        };
+        let Some(data_m) = self.read(volume.path_table_m_lba, size) else {
+            return;
+        };
+
+        let Ok(path_table_l) =
+            PathTableRecord::parse_table::<LittleEndian>(&data_l)
+        else {
+            self.error(volume.path_table_l_lba, "invalid path table record");
+            return;
+        };
+        let Ok(path_table_m) =
+            PathTableRecord::parse_table::<BigEndian>(&data_m)
+        else {
+            self.error(volume.path_table_m_lba, "invalid path table record");
+            return;
+        };
+
+        if path_table_l != path_table_m {
+            self.error(
+                volume.path_table_m_lba,
+                "little and big endian path tables differ",
+            );
+        }
+
+        for (lba, path_table) in [
+            (volume.path_table_l_lba, &path_table_l),
+            (volume.path_table_m_lba, &path_table_m),
+        ] {
+            if !is_path_table_ordered(path_table, volume.hierarchy) {
+                self.error(lba, "path table isn't ordered");
+            }
+        }
+
+        if path_table_l.len() != directories.len() {
+            self.error(
+                volume.path_table_l_lba,
+                format!(
+                    "path table has {} directories instead of {}",
+                    path_table_l.len(),
+                    directories.len()
+                ),
+            );
+            return;
+        }
+
+        for (number, (record, directory)) in
+            path_table_l.iter().zip(directories).enumerate()
+        {
+            if record != directory {
+                self.error(
+                    volume.path_table_l_lba,
+                    format!(
+                        "path table record {} doesn't match its directory at \
+                         LBA {}",
+                        number + 1,
+                        directory.lba
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Check that the Rock Ridge placeholders and relocated directories point
+    /// to each other
+    fn verify_relocations(&mut self) {
+        let links = std::mem::take(&mut self.links);
+
+        for (&lba, &parent_lba) in &links.child_links {
+            if !links.relocated.contains(&lba) {
+                self.error(lba, "CL entry target without RE entry");
+            }
+            if links.parent_links.get(&lba) != Some(&parent_lba) {
+                self.error(
+                    lba,
+                    "PL entry doesn't point to the CL entry parent",
+                );
+            }
+        }
+
+        for &lba in &links.relocated {
+            if !links.child_links.contains_key(&lba) {
+                self.error(lba, "relocated directory without CL entry");
+            }
+        }
+    }
+}
+
+/// Check an image against ECMA-119 and the SUSP/Rock Ridge specifications:
+/// volume descriptors, block alignment and length of the directory records,
+/// both-endian fields, path tables against the directory hierarchy, extents
+/// inside the volume and system use entries
+pub fn verify<R>(mut image: R) -> Vec<Diagnostic>
+where
+    R: Read + Seek,
+{
+    let image_size = match image.seek(SeekFrom::End(0)) {
+        Ok(image_size) => image_size,
+        Err(err) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                lba: None,
+                message: format!("I/O error: {err}"),
+            }];
+        }
+    };
+
+    let mut verifier = Verifier {
+        reader: image,
+        image_size,
+        volume_size: 0,
+        susp_skip: None,
+        has_er: false,
+        links: RelocationLinks::default(),
+        diagnostics: Vec::new(),
+    };
+
+    let volumes = verifier.verify_volume_descriptors();
+    let Some(primary) =
+        volumes.iter().find(|x| x.hierarchy == Hierarchy::Primary)
+    else {
+        verifier.report(
+            Severity::Error,
+            None,
+            "missing primary volume descriptor",
+        );
+        return verifier.diagnostics;
+    };
+
+    verifier.volume_size = primary.volume_size;
+    if u64::from(primary.volume_size) * u64::from(LOGIC_SIZE_U32) > image_size {
+        verifier.error(primary.lba, "volume bigger than the image");
+    }
+
+    for volume in &volumes {
+        if volume.volume_size != primary.volume_size {
+            verifier.error(volume.lba, "volume space sizes differ");
+        }
+
+        verifier.verify_hierarchy(volume);
+    }
+
+    if verifier.susp_skip.is_some() && !verifier.has_er {
+        verifier.warning(primary.root_lba, "SUSP used without ER entry");
+    }
+
+    verifier.diagnostics
+}
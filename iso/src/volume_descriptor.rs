@@ -3,17 +3,53 @@ use chrono::prelude::*;
 
 use crate::directory_entry::DirectoryEntry;
 use crate::file_entry::FileEntry;
-use crate::utils::LOGIC_SIZE_U16;
+use crate::option::VolumeOpt;
+use crate::rock_ridge::ContinuationAreas;
+use crate::utils::{self, Hierarchy, LOGIC_SIZE_U16};
 
 use std::borrow::Cow;
 use std::io::prelude::*;
 
+fn write_identifier<T>(
+    output_writter: &mut T,
+    value: &Option<String>,
+    len: usize,
+    hierarchy: Hierarchy,
+) -> std::io::Result<()>
+where
+    T: Write,
+{
+    let value = value.as_deref().unwrap_or_default();
+    utils::write_padded_str(output_writter, value.as_bytes(), len, hierarchy)
+}
+
+/// Write a 17 bytes volume descriptor date (ECMA-119 8.4.26.1), all zeros
+/// digits when not specified
+fn write_volume_datetime<T>(
+    output_writter: &mut T,
+    value: Option<DateTime<Utc>>,
+) -> std::io::Result<()>
+where
+    T: Write,
+{
+    match value {
+        Some(value) => output_writter
+            .write_all(value.format("%Y%m%d%H%M%S00").to_string().as_bytes())?,
+        None => output_writter.write_all(&[0x30; 16])?,
+    }
+
+    // GMT offset
+    output_writter.write_u8(0)?;
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum VolumeDescriptor {
     Boot,
     Primary(Cow<'static, [u8]>),
-    Supplementary,
+    Supplementary(Cow<'static, [u8]>),
     Volume,
     End,
 }
@@ -23,7 +59,7 @@ impl VolumeDescriptor {
         match self {
             VolumeDescriptor::Boot => 0,
             VolumeDescriptor::Primary(_) => 1,
-            VolumeDescriptor::Supplementary => 2,
+            VolumeDescriptor::Supplementary(_) => 2,
             VolumeDescriptor::Volume => 3,
             VolumeDescriptor::End => 0xff,
         }
@@ -49,12 +85,19 @@ impl VolumeDescriptor {
         root_dir: &mut DirectoryEntry,
         path_table_start_lba: u32,
         size_in_lb: u32,
+        creation_time: DateTime<Utc>,
+        volume_opt: &VolumeOpt,
     ) -> std::io::Result<()>
     where
         T: Write + Seek,
     {
         self.write_volume_header(output_writter)?;
 
+        let hierarchy = match self {
+            VolumeDescriptor::Supplementary(_) => Hierarchy::Joliet,
+            _ => Hierarchy::Primary,
+        };
+
         match self {
             VolumeDescriptor::Boot => {
                 // TODO: write it correctly
@@ -71,16 +114,17 @@ impl VolumeDescriptor {
                 let empty_data_2: [u8; 0x7b5] = [0; 0x7b5];
                 output_writter.write_all(&empty_data_2)?;
             }
-            VolumeDescriptor::Primary(name) => {
+            VolumeDescriptor::Primary(name)
+            | VolumeDescriptor::Supplementary(name) => {
                 output_writter.write_u8(0)?;
 
-                let system_identifier: [u8; 32] = [0x20; 32];
-                output_writter.write_all(&system_identifier)?;
-
-                let mut name_buf = [b' '; 32];
-                let len = name.len().min(32);
-                name_buf[..len].copy_from_slice(&name[..len]);
-                output_writter.write_all(&name_buf)?;
+                write_identifier(
+                    output_writter,
+                    &volume_opt.system_identifier,
+                    32,
+                    hierarchy,
+                )?;
+                utils::write_padded_str(output_writter, name, 32, hierarchy)?;
                 output_writter.write_u64::<LittleEndian>(0)?;
 
                 // Size of the volume in LB
@@ -88,8 +132,12 @@ impl VolumeDescriptor {
                     output_writter.write_u32(size_in_lb)?;
                 }
 
-                let zero_b32: [u8; 32] = [0; 32];
-                output_writter.write_all(&zero_b32)?;
+                // Escape sequences (UCS-2 Level 3 for Joliet)
+                let mut escape_sequences: [u8; 32] = [0; 32];
+                if hierarchy == Hierarchy::Joliet {
+                    escape_sequences[..3].copy_from_slice(b"%/E");
+                }
+                output_writter.write_all(&escape_sequences)?;
 
                 // Disc count
                 write_bothendian! {
@@ -106,51 +154,75 @@ impl VolumeDescriptor {
                     output_writter.write_u16(LOGIC_SIZE_U16)?;
                 }
 
-                let path_table_size = root_dir.get_path_table_size();
+                let path_table_size = root_dir.get_path_table_size(hierarchy);
                 write_bothendian! {
                     output_writter.write_u32(path_table_size)?;
                 }
 
-                // path table location (in lba)
-                let path_table_lba_le = path_table_start_lba; // System Area + Primary + End
-                let path_table_lba_be = path_table_start_lba + 2; // System Area + Primary + End + Path Table LE + Spacing
+                // path table location (in lba), the big endian table is just
+                // after the little endian one
+                let path_table_lba_le = path_table_start_lba;
+                let path_table_lba_be = path_table_start_lba
+                    + root_dir.get_path_table_size_in_lb(hierarchy);
 
                 output_writter.write_u32::<LittleEndian>(path_table_lba_le)?;
                 output_writter.write_u32::<LittleEndian>(0)?;
                 output_writter.write_u32::<BigEndian>(path_table_lba_be)?;
                 output_writter.write_u32::<BigEndian>(0)?;
 
-                root_dir.write_as_current(output_writter, 5)?;
-
-                let volume_set_identifier: [u8; 128] = [0x20; 128];
-                let publisher_identifier: [u8; 128] = [0x20; 128];
-                let data_preparer_identifier: [u8; 128] = [0x20; 128];
-                let application_identifier: [u8; 128] = [0x20; 128];
-                output_writter.write_all(&volume_set_identifier)?;
-                output_writter.write_all(&publisher_identifier)?;
-                output_writter.write_all(&data_preparer_identifier)?;
-                output_writter.write_all(&application_identifier)?;
-
-                let copyright_file_identifier: [u8; 38] = [0x20; 38];
-                let abstract_file_identifier: [u8; 36] = [0x20; 36];
-                let bibliographic_file_identifier: [u8; 37] = [0x20; 37];
-                output_writter.write_all(&copyright_file_identifier)?;
-                output_writter.write_all(&abstract_file_identifier)?;
-                output_writter.write_all(&bibliographic_file_identifier)?;
-
-                let utc: DateTime<Utc> = Utc::now();
-                let creation_time: String =
-                    utc.format("%Y%m%d%H%M%S00").to_string();
-                let expiration_time: [u8; 16] = [0x30; 16];
-
-                output_writter.write_all(creation_time.as_bytes())?;
-                output_writter.write_u8(0)?;
-                output_writter.write_all(creation_time.as_bytes())?;
-                output_writter.write_u8(0)?;
-                output_writter.write_all(&expiration_time)?;
-                output_writter.write_u8(0)?;
-                output_writter.write_all(&expiration_time)?;
-                output_writter.write_u8(0)?;
+                // The root record of the volume descriptor doesn't have any
+                // system use
+                root_dir.write_as_current(
+                    output_writter,
+                    5,
+                    hierarchy,
+                    &mut ContinuationAreas::default(),
+                )?;
+
+                // Volume set, publisher, data preparer and application
+                // identifiers
+                for identifier in [
+                    &volume_opt.volume_set_identifier,
+                    &volume_opt.publisher_identifier,
+                    &volume_opt.data_preparer_identifier,
+                    &volume_opt.application_identifier,
+                ] {
+                    write_identifier(
+                        output_writter,
+                        identifier,
+                        128,
+                        hierarchy,
+                    )?;
+                }
+
+                // Copyright, abstract and bibliographic file identifiers
+                for identifier in [
+                    &volume_opt.copyright_file_identifier,
+                    &volume_opt.abstract_file_identifier,
+                    &volume_opt.bibliographic_file_identifier,
+                ] {
+                    write_identifier(
+                        output_writter,
+                        identifier,
+                        37,
+                        hierarchy,
+                    )?;
+                }
+
+                // Creation, modification, expiration and effective dates
+                write_volume_datetime(output_writter, Some(creation_time))?;
+                write_volume_datetime(
+                    output_writter,
+                    Some(volume_opt.modification_time.unwrap_or(creation_time)),
+                )?;
+                write_volume_datetime(
+                    output_writter,
+                    volume_opt.expiration_time,
+                )?;
+                write_volume_datetime(
+                    output_writter,
+                    volume_opt.effective_time,
+                )?;
 
                 // File structure version
                 output_writter.write_u8(0x1)?;
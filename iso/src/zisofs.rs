@@ -0,0 +1,150 @@
+use crate::utils;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use sha2::{Digest, Sha256};
+
+use std::io;
+use std::io::prelude::*;
+
+/// Magic number starting a file compressed with zisofs
+pub const MAGIC: [u8; 8] = [0x37, 0xE4, 0x53, 0x96, 0xC9, 0xDB, 0xD6, 0x07];
+
+/// Size of the zisofs file header, before the block pointers
+pub const HEADER_SIZE: usize = 0x10;
+
+/// Blocks of 32 KiB, the size used by mkisofs
+pub const LOG2_BLOCK_SIZE: u8 = 15;
+const BLOCK_SIZE: usize = 1 << LOG2_BLOCK_SIZE;
+
+/// File stored compressed with zisofs: a header, a table of pointers to the
+/// blocks, then every block compressed on its own. The blocks are kept from
+/// sizing the file, so its content is only read and compressed once
+#[derive(Debug, Clone)]
+pub struct ZisofsFile {
+    pub uncompressed_size: u32,
+    /// SHA-256 digest of the original content, which isn't read again
+    pub sha256: [u8; 32],
+    /// Header and block pointers
+    header: Vec<u8>,
+    /// Every compressed block, empty for the blocks of zeros
+    blocks: Vec<Vec<u8>>,
+}
+
+/// Compress a block as a zlib stream, blocks of zeros take no space at all
+/// except the last one, libarchive would end the file before it
+fn compress_block(block: &[u8], is_last: bool) -> io::Result<Vec<u8>> {
+    if !is_last && block.iter().all(|&x| x == 0) {
+        return Ok(Vec::new());
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(block)?;
+    encoder.finish()
+}
+
+/// Header and block pointers, pointers are offsets from the start of the
+/// file and a block ends where the next one starts
+fn get_header(
+    uncompressed_size: u32,
+    blocks: &[Vec<u8>],
+) -> io::Result<Vec<u8>> {
+    let mut res = Vec::new();
+    res.write_all(&MAGIC)?;
+    res.write_u32::<LittleEndian>(uncompressed_size)?;
+    res.write_u8((HEADER_SIZE / 4).try_into().unwrap())?;
+    res.write_u8(LOG2_BLOCK_SIZE)?;
+    res.write_all(&[0; 2])?;
+
+    let mut offset =
+        u32::try_from(HEADER_SIZE + 4 * (blocks.len() + 1)).unwrap();
+    res.write_u32::<LittleEndian>(offset)?;
+    for block in blocks {
+        offset += u32::try_from(block.len()).unwrap();
+        res.write_u32::<LittleEndian>(offset)?;
+    }
+
+    Ok(res)
+}
+
+impl ZisofsFile {
+    /// Compress `size` bytes of `content`, `None` if it wouldn't be smaller
+    /// than the original
+    pub fn new<R>(mut content: R, size: usize) -> io::Result<Option<Self>>
+    where
+        R: Read,
+    {
+        // The original size is recorded on 32 bits
+        let Ok(uncompressed_size) = u32::try_from(size) else {
+            return Ok(None);
+        };
+
+        let mut digest = Sha256::new();
+        let mut blocks = Vec::new();
+        let mut compressed_size = HEADER_SIZE + 4;
+        let mut buff = vec![0; BLOCK_SIZE];
+        let mut remaining_size = size;
+        while remaining_size != 0 {
+            let block = &mut buff[..remaining_size.min(BLOCK_SIZE)];
+            if utils::read_full(&mut content, block)? != block.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file is smaller than expected",
+                ));
+            }
+            digest.update(&*block);
+
+            remaining_size -= block.len();
+            let compressed = compress_block(block, remaining_size == 0)?;
+            compressed_size += 4 + compressed.len();
+            // No need to go on once it's known not to be smaller
+            if compressed_size >= size {
+                return Ok(None);
+            }
+            blocks.push(compressed);
+        }
+        if compressed_size >= size {
+            return Ok(None);
+        }
+
+        Ok(Some(ZisofsFile {
+            uncompressed_size,
+            sha256: digest.finalize().into(),
+            header: get_header(uncompressed_size, &blocks)?,
+            blocks,
+        }))
+    }
+
+    /// Size of the file once compressed, header included
+    pub fn get_compressed_size(&self) -> usize {
+        self.header.len() + self.blocks.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Compressed content of a `ZisofsFile`, read from its blocks
+pub struct ZisofsReader<'a> {
+    blocks: std::slice::Iter<'a, Vec<u8>>,
+    pending: &'a [u8],
+}
+
+impl<'a> ZisofsReader<'a> {
+    pub fn new(file: &'a ZisofsFile) -> Self {
+        ZisofsReader {
+            blocks: file.blocks.iter(),
+            pending: &file.header,
+        }
+    }
+}
+
+impl Read for ZisofsReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() && !buf.is_empty() {
+            let Some(block) = self.blocks.next() else {
+                return Ok(0);
+            };
+            self.pending = block;
+        }
+        self.pending.read(buf)
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::open;
+use iso::IsoBuilder;
+use iso::option::{ElToritoEntry, ElToritoOpt, Opt, Platform};
+
+fn builder() -> IsoBuilder {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("boot.bin", vec![0x90; 5000]).unwrap();
+    builder
+        .add_buffer("efi.img", vec![0xEE; 1440 * 1024])
+        .unwrap();
+    builder.add_buffer("data.bin", vec![1; 100_000]).unwrap();
+    builder
+}
+
+fn eltorito_opt() -> ElToritoOpt {
+    ElToritoOpt {
+        eltorito_boot: Some("boot.bin".into()),
+        boot_info_table: true,
+        entries: vec![ElToritoEntry {
+            platform: Platform::Efi,
+            boot_file: "efi.img".into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn el_torito() {
+    let opt = Opt {
+        eltorito_opt: eltorito_opt(),
+        boot_load_size: 4,
+        ..Default::default()
+    };
+    let mut image = open(builder().build(&opt).unwrap());
+    image.verify_boot_catalog().unwrap();
+
+    let catalog = image.boot_catalog.clone().unwrap();
+    assert_eq!(catalog.entries.len(), 2);
+    assert_eq!(catalog.entries[0].sector_count, 4);
+    assert_eq!(
+        catalog.entries[0].load_rba,
+        image.root.get_file("boot.bin").unwrap().record.lba
+    );
+    // The whole image, in 512 bytes sectors
+    assert_eq!(catalog.entries[1].platform_id, 0xEF);
+    assert_eq!(catalog.entries[1].sector_count, 2880);
+    assert_eq!(
+        catalog.entries[1].load_rba,
+        image.root.get_file("efi.img").unwrap().record.lba
+    );
+    assert_eq!(image.read_file("data.bin").unwrap(), vec![1; 100_000]);
+}
+
+#[test]
+fn gpt() {
+    let opt = Opt {
+        eltorito_opt: eltorito_opt(),
+        boot_load_size: 4,
+        gpt: true,
+        ..Default::default()
+    };
+    let data = builder().build(&opt).unwrap();
+
+    // Protective MBR, then the primary and backup GPT headers
+    assert_eq!(data[0x1FE..0x200], [0x55, 0xAA]);
+    assert_eq!(data[0x1C2], 0xEE);
+    assert_eq!(&data[0x200..0x208], b"EFI PART");
+    assert_eq!(&data[data.len() - 0x200..][..8], b"EFI PART");
+
+    let mut image = open(data);
+    image.verify_boot_catalog().unwrap();
+    assert_eq!(image.read_file("data.bin").unwrap(), vec![1; 100_000]);
+}
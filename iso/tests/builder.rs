@@ -0,0 +1,56 @@
+mod common;
+
+use chrono::DateTime;
+use common::open;
+use iso::option::{Opt, Timestamp};
+use iso::{IsoBuilder, IsoError};
+
+fn builder() -> IsoBuilder {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("a.txt", b"a".to_vec()).unwrap();
+    builder.add_buffer("dir/b.txt", b"b".to_vec()).unwrap();
+    builder
+}
+
+#[test]
+fn rename() {
+    let mut builder = builder();
+    builder.rename("a.txt", "new/parent/a.txt").unwrap();
+    builder.rename("dir", "moved/dir").unwrap();
+
+    let mut image = open(builder.build(&Opt::default()).unwrap());
+    assert_eq!(image.read_file("new/parent/a.txt").unwrap(), b"a");
+    assert_eq!(image.read_file("moved/dir/b.txt").unwrap(), b"b");
+    assert!(image.root.get_file("a.txt").is_none());
+    assert!(image.root.get_directory("dir").is_none());
+}
+
+#[test]
+fn failed_rename_leaves_the_tree() {
+    let mut builder = builder();
+    assert!(matches!(
+        builder.rename("a.txt", "dir/b.txt"),
+        Err(IsoError::AlreadyExists(_))
+    ));
+    assert!(matches!(
+        builder.rename("dir", "a.txt/new/dir"),
+        Err(IsoError::NotADirectory(_))
+    ));
+    assert!(matches!(
+        builder.rename("dir", "dir/new/dir"),
+        Err(IsoError::InvalidPath(_))
+    ));
+    assert!(matches!(
+        builder.rename("missing", "new/missing"),
+        Err(IsoError::NotFound(_))
+    ));
+
+    let opt = Opt {
+        timestamp: Timestamp::Fixed(DateTime::from_timestamp(0, 0).unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(
+        builder.build(&opt).unwrap(),
+        self::builder().build(&opt).unwrap()
+    );
+}
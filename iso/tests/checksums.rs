@@ -0,0 +1,69 @@
+mod common;
+
+use common::open;
+use iso::option::Opt;
+use iso::{IsoBuilder, IsoError, MANIFEST_NAME, Severity, verify_checksums};
+
+use std::io::Cursor;
+
+fn builder() -> IsoBuilder {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("a/tex.png", vec![9; 10_000]).unwrap();
+    builder.add_buffer("b/tex.png", vec![9; 10_000]).unwrap();
+    builder
+        .add_buffer("text.txt", b"hello world\n".repeat(5000))
+        .unwrap();
+    builder.add_buffer("empty", Vec::new()).unwrap();
+    builder
+        .add_buffer("1/2/3/4/5/6/7/8/9/deep.txt", b"deep".to_vec())
+        .unwrap();
+    builder
+}
+
+fn opt() -> Opt {
+    Opt {
+        checksums: true,
+        deduplicate: true,
+        compressed_files: vec!["*.txt".into()],
+        joliet: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn manifest_and_image_digests() {
+    let data = builder().build(&opt()).unwrap();
+    assert!(verify_checksums(Cursor::new(&data)).is_empty());
+
+    let mut image = open(data.clone());
+    let manifest =
+        String::from_utf8(image.read_file(MANIFEST_NAME).unwrap()).unwrap();
+    assert_eq!(manifest.lines().count(), 5);
+    assert!(manifest.contains("  1/2/3/4/5/6/7/8/9/deep.txt\n"));
+
+    // Both deduplicated files share the corrupted content
+    let lba = image.root.get_file("a/tex.png").unwrap().extents[0].0;
+    let offset = usize::try_from(lba).unwrap() * 2048 + 3;
+    let mut bad = data;
+    bad[offset] ^= 1;
+    let errors = verify_checksums(Cursor::new(&bad))
+        .into_iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    assert_eq!(errors, 4);
+}
+
+#[test]
+fn write_to_with_checksums() {
+    let mut out = Cursor::new(Vec::new());
+    builder().write_to_with_checksums(&opt(), &mut out).unwrap();
+    assert!(verify_checksums(Cursor::new(out.get_ref())).is_empty());
+    assert_eq!(out.into_inner(), builder().build(&opt()).unwrap());
+
+    // The digests can't be implanted without reading the image back
+    let mut out = Cursor::new(Vec::new());
+    assert!(matches!(
+        builder().write_to(&opt(), &mut out),
+        Err(IsoError::ChecksumsNeedReadableOutput)
+    ));
+}
@@ -0,0 +1,22 @@
+// Every test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use iso::reader::IsoImage;
+use iso::{Severity, verify};
+
+use std::io::Cursor;
+
+/// Check that [verify] finds no error in `data`
+pub fn assert_valid(data: &[u8]) {
+    let errors: Vec<_> = verify(Cursor::new(data))
+        .into_iter()
+        .filter(|x| x.severity == Severity::Error)
+        .collect();
+    assert!(errors.is_empty(), "{errors:?}");
+}
+
+/// Check and open an image held in memory
+pub fn open(data: Vec<u8>) -> IsoImage<Cursor<Vec<u8>>> {
+    assert_valid(&data);
+    iso::read_iso(Cursor::new(data)).unwrap()
+}
@@ -0,0 +1,43 @@
+use iso::IsoBuilder;
+use iso::option::Opt;
+
+use std::io::{Cursor, ErrorKind};
+
+/// Offset of the root directory record in the primary volume descriptor
+const ROOT_RECORD: usize = 16 * 2048 + 156;
+
+fn image() -> Vec<u8> {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("file.txt", b"file".to_vec()).unwrap();
+    builder.build(&Opt::default()).unwrap()
+}
+
+fn assert_invalid(data: Vec<u8>) {
+    match iso::read_iso(Cursor::new(data)) {
+        Ok(_) => panic!("corrupted image opened"),
+        Err(err) => assert_eq!(err.kind(), ErrorKind::InvalidData),
+    }
+}
+
+#[test]
+fn empty_root_directory() {
+    let mut data = image();
+    data[ROOT_RECORD + 10..ROOT_RECORD + 18].fill(0);
+    assert_invalid(data);
+}
+
+#[test]
+fn short_directory_record() {
+    let mut data = image();
+    let lba = u32::from_le_bytes(
+        data[ROOT_RECORD + 2..ROOT_RECORD + 6].try_into().unwrap(),
+    );
+    let offset = usize::try_from(lba).unwrap() * 2048;
+    data[offset] = 0x10;
+    assert_invalid(data);
+
+    // The record length itself is cut
+    let mut data = image();
+    data[ROOT_RECORD] = 0x10;
+    assert_invalid(data);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::open;
+use iso::IsoBuilder;
+use iso::option::Opt;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Empty directory for the test `name`
+fn scratch_directory(name: &str) -> PathBuf {
+    let path = std::env::temp_dir()
+        .join(format!("iso-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn extract_image() {
+    let mut builder = IsoBuilder::new();
+    builder
+        .add_buffer("dir/file.txt", b"file".to_vec())
+        .unwrap();
+    builder
+        .add_buffer("dir/sub/other.txt", b"other".to_vec())
+        .unwrap();
+    builder.add_symlink("link", "dir/file.txt").unwrap();
+    let mut image = open(builder.build(&Opt::default()).unwrap());
+
+    let scratch = scratch_directory("extract");
+    let out = scratch.join("out");
+    image.extract(&out).unwrap();
+    assert_eq!(fs::read(out.join("dir/file.txt")).unwrap(), b"file");
+    assert_eq!(fs::read(out.join("dir/sub/other.txt")).unwrap(), b"other");
+    #[cfg(unix)]
+    assert_eq!(
+        fs::read_link(out.join("link")).unwrap(),
+        PathBuf::from("dir/file.txt")
+    );
+
+    // Nothing existing is overwritten
+    assert!(image.extract(&out).is_err());
+    fs::remove_dir_all(scratch).unwrap();
+}
+
+#[test]
+fn extract_never_follows_symlinks() {
+    let scratch = scratch_directory("symlinks");
+    let outside = scratch.join("outside");
+    fs::create_dir(&outside).unwrap();
+
+    let mut builder = IsoBuilder::new();
+    builder
+        .add_symlink("evil", outside.to_str().unwrap())
+        .unwrap();
+    builder.add_buffer("evim/pwned", b"pwned".to_vec()).unwrap();
+    let mut data = builder.build(&Opt::default()).unwrap();
+
+    // Give the directory the Rock Ridge name of the symbolic link
+    let pos = data.windows(4).position(|x| x == b"evim").unwrap();
+    data[pos + 3] = b'l';
+
+    let mut image = open(data);
+    assert!(image.extract(scratch.join("out")).is_err());
+    assert!(!outside.join("pwned").exists());
+    fs::remove_dir_all(scratch).unwrap();
+}
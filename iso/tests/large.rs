@@ -0,0 +1,112 @@
+mod common;
+
+use common::open;
+use iso::IsoBuilder;
+use iso::option::{InterchangeLevel, Opt};
+use iso::{IsoError, Severity, verify};
+
+use std::io::Cursor;
+
+#[test]
+fn thousands_of_files() {
+    let mut builder = IsoBuilder::new();
+    for i in 0..3000 {
+        builder
+            .add_buffer(
+                &format!("big/file_with_a_long_name_{i:05}.txt"),
+                i.to_string().into_bytes(),
+            )
+            .unwrap();
+    }
+    for i in 0..400 {
+        builder
+            .add_buffer(&format!("d{i:03}_directory_name/sub/f.txt"), vec![1])
+            .unwrap();
+    }
+
+    let opt = Opt {
+        joliet: true,
+        interchange_level: InterchangeLevel::Level2,
+        ..Default::default()
+    };
+    let mut image = open(builder.build(&opt).unwrap());
+
+    // Directories and path tables span several logical blocks
+    let big = image.root.get_directory("big").unwrap().clone();
+    assert!(big.record.data_length > 10 * 2048);
+    assert!(image.primary.path_table_size > 4 * 2048);
+    assert!(image.joliet.as_ref().unwrap().path_table_size > 4 * 2048);
+
+    assert_eq!(image.path_table_l, image.path_table_m);
+    assert_eq!(image.path_table_l.len(), 2 + 2 * 400);
+    for (i, record) in image.path_table_l.iter().enumerate() {
+        assert!(usize::from(record.parent_index) <= i.max(1));
+    }
+
+    assert_eq!(big.files.len(), 3000);
+    for i in 0..3000 {
+        let path = format!("big/file_with_a_long_name_{i:05}.txt");
+        assert_eq!(image.read_file(&path).unwrap(), i.to_string().as_bytes());
+    }
+    for i in 0..400 {
+        let path = format!("d{i:03}_directory_name/sub/f.txt");
+        assert_eq!(image.read_file(&path).unwrap(), [1]);
+    }
+
+    let joliet_root = image.joliet_root.as_ref().unwrap();
+    assert_eq!(joliet_root.get_directory("big").unwrap().files.len(), 3000);
+    assert_eq!(joliet_root.directories.len(), 401);
+}
+
+#[test]
+fn too_many_directories() {
+    let mut builder = IsoBuilder::new();
+    for i in 0..256 {
+        for j in 0..256 {
+            builder.add_directory(&format!("d{i}/d{j}")).unwrap();
+        }
+    }
+
+    // The parents of the path table records are numbered with 16 bits
+    let mut out = Cursor::new(Vec::new());
+    assert!(matches!(
+        builder.write_to(&Opt::default(), &mut out),
+        Err(IsoError::TooManyDirectories)
+    ));
+}
+
+#[test]
+fn records_and_path_tables_are_sorted() {
+    let mut builder = IsoBuilder::new();
+    builder.add_directory("ZETA/B").unwrap();
+    builder.add_directory("ZETA/A").unwrap();
+    builder.add_directory("ALPHA").unwrap();
+    builder.add_buffer("MID.TXT", b"mid".to_vec()).unwrap();
+    builder.add_buffer("AAA.TXT", b"aaa".to_vec()).unwrap();
+
+    let opt = Opt {
+        joliet: true,
+        ..Default::default()
+    };
+    let data = builder.build(&opt).unwrap();
+    assert!(verify(Cursor::new(&data)).is_empty());
+
+    let image = open(data.clone());
+    let identifiers: Vec<_> = image
+        .path_table_l
+        .iter()
+        .map(|x| String::from_utf8_lossy(&x.identifier).into_owned())
+        .collect();
+    assert_eq!(identifiers, ["\0", "ALPHA", "ZETA", "A", "B"]);
+
+    let names: Vec<_> = image.root.files.iter().map(|x| &x.name).collect();
+    assert_eq!(names, ["AAA.TXT", "MID.TXT"]);
+
+    // Renaming a directory in place breaks the order of the path tables
+    let pos = data.windows(5).position(|x| x == b"ALPHA").unwrap();
+    let mut bad = data;
+    bad[pos..pos + 5].copy_from_slice(b"ZZZZZ");
+    assert!(verify(Cursor::new(&bad)).iter().any(|x| {
+        x.severity == Severity::Error && x.message.contains("isn't ordered")
+    }));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::open;
+use iso::option::{InterchangeLevel, Opt};
+use iso::{IsoBuilder, Severity, verify};
+
+use std::io::Cursor;
+
+#[test]
+fn colliding_names() {
+    let names = [
+        "texture_floor.png",
+        "texture_wall.png",
+        "texture_wall.PNG",
+        "texture wall.png",
+        "Readme",
+        "archive.tar.gz",
+    ];
+
+    for level in [InterchangeLevel::Level1, InterchangeLevel::Level2] {
+        let mut builder = IsoBuilder::new();
+        for name in names {
+            builder.add_buffer(name, name.as_bytes().to_vec()).unwrap();
+        }
+        builder.add_directory("readme").unwrap();
+        builder
+            .add_directory("a-long-directory-name-over-31-characters")
+            .unwrap();
+        builder
+            .add_directory("a-long-directory-name-over-31-characters-2")
+            .unwrap();
+
+        let opt = Opt {
+            interchange_level: level,
+            joliet: true,
+            ..Default::default()
+        };
+        let data = builder.build(&opt).unwrap();
+        // Also checks that the identifiers only use d-characters
+        assert!(verify(Cursor::new(&data)).is_empty());
+
+        let mut image = open(data);
+        let mut identifiers: Vec<_> = image
+            .root
+            .files
+            .iter()
+            .map(|x| x.record.identifier.clone())
+            .chain(
+                image
+                    .root
+                    .directories
+                    .iter()
+                    .map(|x| x.record.identifier.clone()),
+            )
+            .collect();
+        identifiers.sort();
+        identifiers.dedup();
+        assert_eq!(identifiers.len(), names.len() + 3);
+
+        for name in names {
+            assert_eq!(image.read_file(name).unwrap(), name.as_bytes());
+        }
+    }
+}
+
+#[test]
+fn identifiers_outside_of_d_characters() {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("name.txt", b"name".to_vec()).unwrap();
+    let mut data = builder.build(&Opt::default()).unwrap();
+
+    let pos = data.windows(8).position(|x| x == b"NAME.TXT").unwrap();
+    data[pos + 2] = b'~';
+    assert!(verify(Cursor::new(&data)).iter().any(|x| {
+        x.severity == Severity::Error && x.message.contains("d-characters")
+    }));
+}
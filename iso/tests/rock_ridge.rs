@@ -0,0 +1,106 @@
+mod common;
+
+use common::open;
+use iso::IsoBuilder;
+use iso::option::Opt;
+
+use std::io::prelude::*;
+
+#[test]
+fn names_and_symlinks() {
+    let long_name = format!("{}.Data", "Long Name ".repeat(22));
+    let target = (0..20)
+        .map(|i| format!("{i}{}", "t".repeat(200)))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let mut builder = IsoBuilder::new();
+    builder
+        .add_buffer("lower/MiXeD.txt", b"mixed".to_vec())
+        .unwrap();
+    builder
+        .add_buffer(&format!("lower/{long_name}"), vec![2])
+        .unwrap();
+    builder.add_buffer("lower/archive.tar.gz", vec![3]).unwrap();
+    builder.add_symlink("lower/link", "MiXeD.txt").unwrap();
+    builder
+        .add_symlink("lower/absolute", "/etc/../tmp/./x")
+        .unwrap();
+    builder.add_symlink("lower/huge", &target).unwrap();
+
+    let mut image = open(builder.build(&Opt::default()).unwrap());
+
+    assert_eq!(image.read_file("lower/MiXeD.txt").unwrap(), b"mixed");
+    assert_eq!(image.read_file(&format!("lower/{long_name}")).unwrap(), [2]);
+    assert_eq!(image.read_file("lower/archive.tar.gz").unwrap(), [3]);
+
+    let file = image.root.get_file("lower/MiXeD.txt").unwrap();
+    assert_eq!(file.record.identifier, b"MIXED.TXT;1");
+    let mode = file.record.rock_ridge.posix.as_ref().unwrap().mode;
+    assert_eq!(mode & 0o170000, 0o100000);
+
+    let symlink = |path| {
+        let file = image.root.get_file(path).unwrap();
+        let mode = file.record.rock_ridge.posix.as_ref().unwrap().mode;
+        assert_eq!(mode & 0o170000, 0o120000);
+        file.record.rock_ridge.symlink.clone().unwrap()
+    };
+    assert_eq!(symlink("lower/link"), "MiXeD.txt");
+    assert_eq!(symlink("lower/absolute"), "/etc/../tmp/./x");
+    // Spans several 'SL' entries and continuation areas
+    assert_eq!(symlink("lower/huge"), target);
+}
+
+#[test]
+fn deep_directories_are_relocated() {
+    let levels: Vec<_> = (1..=12).map(|i| format!("level{i}")).collect();
+    let path = levels.join("/");
+
+    let mut builder = IsoBuilder::new();
+    builder
+        .add_buffer(&format!("{path}/leaf.txt"), b"leaf".to_vec())
+        .unwrap();
+    builder
+        .add_buffer(&format!("{}/mid.txt", levels[..9].join("/")), vec![9])
+        .unwrap();
+
+    let opt = Opt {
+        joliet: true,
+        ..Default::default()
+    };
+    let mut image = open(builder.build(&opt).unwrap());
+
+    // Rock Ridge shows the original hierarchy
+    assert_eq!(
+        image.read_file(&format!("{path}/leaf.txt")).unwrap(),
+        b"leaf"
+    );
+    assert_eq!(
+        image
+            .read_file(&format!("{}/mid.txt", levels[..9].join("/")))
+            .unwrap(),
+        [9]
+    );
+
+    // The primary hierarchy stops at 8 levels, the root being the first
+    assert!(image.root.get_directory("rr_moved").is_some());
+    let path_table = &image.path_table_l;
+    for number in 1..=path_table.len() {
+        let mut level = 1;
+        let mut index = number;
+        while index != 1 {
+            index = usize::from(path_table[index - 1].parent_index);
+            level += 1;
+        }
+        assert!(level <= 8);
+    }
+
+    // Joliet keeps the relocated directories at their original place
+    let joliet_root = image.joliet_root.as_ref().unwrap();
+    assert!(joliet_root.get_directory("rr_moved").is_none());
+    let leaf = joliet_root.get_file(&format!("{path}/leaf.txt")).unwrap();
+    let leaf = leaf.clone();
+    let mut content = Vec::new();
+    image.open_file(&leaf).read_to_end(&mut content).unwrap();
+    assert_eq!(content, b"leaf");
+}
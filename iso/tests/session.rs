@@ -0,0 +1,162 @@
+mod common;
+
+use common::open;
+use iso::option::{ElToritoOpt, Opt};
+use iso::{IsoBuilder, IsoError, verify_checksums};
+
+use std::fs::{self, File};
+use std::io::Cursor;
+
+#[test]
+fn append_sessions() {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("a.txt", b"old a".to_vec()).unwrap();
+    builder.add_buffer("dir/b.txt", b"old b".to_vec()).unwrap();
+    builder
+        .add_buffer("dir/z.txt", b"z".repeat(60_000))
+        .unwrap();
+    builder.add_symlink("link", "dir/b.txt").unwrap();
+    builder
+        .add_buffer("1/2/3/4/5/6/7/8/9/deep.txt", b"deep".to_vec())
+        .unwrap();
+
+    let opt = Opt {
+        compressed_files: vec!["dir/z.txt".into()],
+        checksums: true,
+        joliet: true,
+        ..Default::default()
+    };
+    let mut data = Cursor::new(builder.build(&opt).unwrap());
+    let first_size = data.get_ref().len();
+    let old_b = open(data.get_ref().clone())
+        .root
+        .get_file("dir/b.txt")
+        .unwrap()
+        .clone();
+
+    let gpt = Opt {
+        gpt: true,
+        ..Default::default()
+    };
+    assert!(matches!(
+        IsoBuilder::from_image(&mut data)
+            .unwrap()
+            .append_to(&gpt, &mut data),
+        Err(IsoError::UnsupportedInSession(_))
+    ));
+
+    let mut builder = IsoBuilder::from_image(&mut data).unwrap();
+    builder.add_buffer("a.txt", b"new a".to_vec()).unwrap();
+    builder.add_buffer("new/c.txt", b"c".to_vec()).unwrap();
+    let report = builder.append_to(&opt, &mut data).unwrap();
+    let data = data.into_inner();
+    assert_eq!(
+        usize::try_from(report.size_in_lb).unwrap() * 2048,
+        data.len()
+    );
+    // Only the new files and the directories are written again
+    assert!(data.len() - first_size < 80 * 2048);
+    assert!(verify_checksums(Cursor::new(&data)).is_empty());
+
+    let mut image = open(data);
+    assert_eq!(image.read_file("a.txt").unwrap(), b"new a");
+    assert_eq!(image.read_file("dir/b.txt").unwrap(), b"old b");
+    assert_eq!(image.read_file("dir/z.txt").unwrap(), b"z".repeat(60_000));
+    assert_eq!(image.read_file("new/c.txt").unwrap(), b"c");
+    assert_eq!(
+        image.read_file("1/2/3/4/5/6/7/8/9/deep.txt").unwrap(),
+        b"deep"
+    );
+    let link = image.root.get_file("link").unwrap();
+    assert_eq!(link.record.rock_ridge.symlink.as_deref(), Some("dir/b.txt"));
+    let b = image.root.get_file("dir/b.txt").unwrap();
+    assert_eq!(b.extents, old_b.extents);
+    let a = image.root.get_file("a.txt").unwrap();
+    assert!(usize::try_from(a.extents[0].0).unwrap() * 2048 >= first_size);
+    assert!(
+        image
+            .joliet_root
+            .as_ref()
+            .unwrap()
+            .get_file("a.txt")
+            .is_some()
+    );
+}
+
+#[test]
+fn append_keeps_el_torito() {
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("boot.bin", vec![0x90; 2048]).unwrap();
+    let opt = Opt {
+        eltorito_opt: ElToritoOpt {
+            eltorito_boot: Some("boot.bin".into()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut data = Cursor::new(builder.build(&opt).unwrap());
+    let catalog = open(data.get_ref().clone()).boot_catalog.unwrap();
+
+    let mut builder = IsoBuilder::from_image(&mut data).unwrap();
+    builder.add_buffer("added.txt", b"added".to_vec()).unwrap();
+    let opt = Opt {
+        joliet: true,
+        ..Default::default()
+    };
+    builder.append_to(&opt, &mut data).unwrap();
+    let data = data.into_inner();
+
+    // Firmwares only look at logical block 17
+    assert_eq!(
+        &data[17 * 2048..][..30],
+        b"\0CD001\x01EL TORITO SPECIFICATION"
+    );
+
+    let mut image = open(data);
+    assert_eq!(image.boot_catalog.as_ref().unwrap().lba, catalog.lba);
+    image.verify_boot_catalog().unwrap();
+    assert_eq!(image.read_file("added.txt").unwrap(), b"added");
+    assert!(image.joliet.is_some());
+}
+
+#[test]
+fn append_past_4_gib() {
+    let path = std::env::temp_dir()
+        .join(format!("iso-test-{}-large-session.iso", std::process::id()));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("first.txt", b"first".to_vec()).unwrap();
+    builder.write_to(&Opt::default(), &mut file).unwrap();
+
+    // Sparse content up to the new session
+    file.set_len(0x20_0000 * 2048 + 1).unwrap();
+
+    let mut builder = IsoBuilder::from_image(&mut file).unwrap();
+    builder
+        .add_buffer("dir/second.txt", b"second".to_vec())
+        .unwrap();
+    let opt = Opt {
+        joliet: true,
+        ..Default::default()
+    };
+    builder.append_to(&opt, &mut file).unwrap();
+
+    let mut image = iso::read_iso(&mut file).unwrap();
+    let second = image.root.get_file("dir/second.txt").unwrap();
+    assert!(second.extents[0].0 > 0x20_0000);
+    assert_eq!(image.read_file("first.txt").unwrap(), b"first");
+    assert_eq!(image.read_file("dir/second.txt").unwrap(), b"second");
+    let joliet_root = image.joliet_root.as_ref().unwrap();
+    assert!(joliet_root.get_file("dir/second.txt").is_some());
+
+    drop(image);
+    drop(file);
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::open;
+use iso::IsoBuilder;
+use iso::option::Opt;
+
+use std::io::SeekFrom;
+use std::io::prelude::*;
+
+fn text(len: usize) -> Vec<u8> {
+    (0..)
+        .flat_map(|i| format!("line {} of text\n", i % 977).into_bytes())
+        .take(len)
+        .collect()
+}
+
+/// Bytes that don't compress
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 12345u32;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            state.to_le_bytes()[2]
+        })
+        .collect()
+}
+
+#[test]
+fn compressed_files() {
+    let text = text(100_000);
+    let noise = noise(50_000);
+    let mut sparse = vec![0; 32768 * 3 + 10];
+    sparse[32768 * 2 + 5] = 7;
+
+    let mut builder = IsoBuilder::new();
+    builder.add_buffer("data/text.txt", text.clone()).unwrap();
+    builder.add_buffer("data/noise.bin", noise.clone()).unwrap();
+    builder
+        .add_buffer("data/sparse.bin", sparse.clone())
+        .unwrap();
+    builder.add_buffer("data/empty.txt", Vec::new()).unwrap();
+    builder.add_buffer("raw.txt", text.clone()).unwrap();
+
+    let opt = Opt {
+        compressed_files: vec!["data/**".into()],
+        joliet: true,
+        ..Default::default()
+    };
+    let mut image = open(builder.build(&opt).unwrap());
+
+    let file = |path| image.root.get_file(path).unwrap().clone();
+    let compressed = file("data/text.txt");
+    assert!(compressed.record.rock_ridge.zisofs.is_some());
+    assert!(compressed.stored_size() < 20_000);
+    assert_eq!(compressed.size(), 100_000);
+    assert!(file("data/sparse.bin").stored_size() < 200);
+    // Kept as is when compressing doesn't help, or isn't asked for
+    assert!(file("data/noise.bin").record.rock_ridge.zisofs.is_none());
+    assert!(file("data/empty.txt").record.rock_ridge.zisofs.is_none());
+    assert!(file("raw.txt").record.rock_ridge.zisofs.is_none());
+
+    assert_eq!(image.read_file("data/text.txt").unwrap(), text);
+    assert_eq!(image.read_file("data/noise.bin").unwrap(), noise);
+    assert_eq!(image.read_file("data/sparse.bin").unwrap(), sparse);
+    assert_eq!(image.read_file("data/empty.txt").unwrap(), b"");
+    assert_eq!(image.read_file("raw.txt").unwrap(), text);
+
+    // Seek inside a block that isn't the first one
+    let mut reader = image.open_file(&compressed);
+    reader.seek(SeekFrom::Start(70_000)).unwrap();
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).unwrap();
+    assert_eq!(content, text[70_000..]);
+}